@@ -0,0 +1,133 @@
+/*
+This file finds the ACPI tables through the UEFI configuration table. The kernel needs the address of the RSDP
+if it cannot find it itself (e.g. when it is started without EFI information) and the shell can list the tables.
+https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#root-system-description-pointer-rsdp
+*/
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID};
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+pub struct Rsdp {
+    pub signature: [u8; 8],
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+    // the following fields only exist for revision >= 2 (ACPI 2.0+)
+    pub length: u32,
+    pub xsdt_address: u64,
+    pub extended_checksum: u8,
+    _reserved: [u8; 3],
+}
+
+// common header of all tables except the RSDP (and FACS)
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+pub struct AcpiTable {
+    pub addr: u64,
+    pub header: SdtHeader,
+    pub checksum_valid: bool,
+}
+
+// prefers the ACPI 2.0 RSDP and falls back to the ACPI 1.0 one
+pub fn rsdp_addr() -> Option<u64> {
+    uefi::system::with_config_table(|entries| {
+        entries
+            .iter()
+            .find(|entry| entry.guid == ACPI2_GUID)
+            .or_else(|| entries.iter().find(|entry| entry.guid == ACPI_GUID))
+            .map(|entry| entry.address as u64)
+    })
+}
+
+pub fn rsdp() -> SimpleResult<Rsdp> {
+    let Some(addr) = rsdp_addr() else {
+        return simple_error!("The firmware does not provide ACPI tables");
+    };
+
+    // an ACPI 1.0 RSDP only has the first 20 bytes, the rest could be beyond the mapped memory
+    const RSDP_V1_SIZE: usize = 20;
+
+    let mut rsdp: Rsdp = unsafe { core::mem::zeroed() };
+    unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, &mut rsdp as *mut Rsdp as *mut u8, RSDP_V1_SIZE) };
+
+    if &rsdp.signature != b"RSD PTR " {
+        return simple_error!("The RSDP at {addr:#x} has an invalid signature");
+    }
+
+    if rsdp.revision >= 2 {
+        rsdp = unsafe { (addr as *const Rsdp).read_unaligned() };
+    }
+    Ok(rsdp)
+}
+
+// returns the XSDT (or RSDT for ACPI 1.0) followed by all tables it points to
+pub fn tables() -> SimpleResult<Vec<AcpiTable>> {
+    let rsdp = rsdp()?;
+
+    let (root_addr, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, 8)
+    } else {
+        (rsdp.rsdt_address as u64, 4)
+    };
+
+    let root = read_table(root_addr);
+    let mut tables = Vec::new();
+
+    let entries_addr = root_addr as usize + core::mem::size_of::<SdtHeader>();
+    let entry_count = (root.header.length as usize).saturating_sub(core::mem::size_of::<SdtHeader>()) / entry_size;
+
+    for i in 0..entry_count {
+        let entry_ptr = entries_addr + i * entry_size;
+        let addr = unsafe {
+            if entry_size == 8 {
+                (entry_ptr as *const u64).read_unaligned()
+            } else {
+                (entry_ptr as *const u32).read_unaligned() as u64
+            }
+        };
+
+        if addr != 0 {
+            tables.push(read_table(addr));
+        }
+    }
+
+    tables.insert(0, root);
+    Ok(tables)
+}
+
+fn read_table(addr: u64) -> AcpiTable {
+    let header = unsafe { (addr as *const SdtHeader).read_unaligned() };
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, header.length as usize) };
+    let checksum_valid = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0;
+
+    AcpiTable { addr, header, checksum_valid }
+}
+
+// the ids are space padded ASCII
+pub fn ascii_to_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+        .collect::<String>()
+        .trim_end()
+        .into()
+}
@@ -0,0 +1,249 @@
+/*
+This file parses Android boot images (boot.img, header versions 0 to 4) and vendor_boot images (versions 3 and 4)
+as used by Android-x86 and Cuttlefish. The embedded kernel is a normal bzImage that is started like any other
+kernel; the ramdisks of both images are concatenated and the command lines are merged.
+https://source.android.com/docs/core/architecture/bootloader/boot-image-header
+*/
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+const BOOT_MAGIC: &[u8] = b"ANDROID!";
+const VENDOR_BOOT_MAGIC: &[u8] = b"VNDRBOOT";
+
+// v3 and newer boot images always use 4K pages
+const BOOT_IMAGE_V3_PAGE_SIZE: usize = 4096;
+
+// the size of the cmdline fields in the headers
+const BOOT_ARGS_SIZE: usize = 512;
+const BOOT_EXTRA_ARGS_SIZE: usize = 1024;
+const VENDOR_BOOT_ARGS_SIZE: usize = 2048;
+
+// appended to the ramdisk after the bootconfig parameters
+// https://www.kernel.org/doc/html/latest/admin-guide/bootconfig.html#attaching-a-boot-config-to-initrd
+const BOOTCONFIG_MAGIC: &[u8] = b"#BOOTCONFIG\n";
+
+pub struct BootImage<'a> {
+    pub header_version: u32,
+    pub kernel: &'a [u8],
+    pub ramdisk: &'a [u8],
+    pub cmdline: String,
+}
+
+pub struct VendorBootImage<'a> {
+    pub header_version: u32,
+    pub ramdisk: &'a [u8],
+    pub cmdline: String,
+    pub bootconfig: &'a [u8],
+}
+
+// what is passed to Kernel::start
+pub struct AndroidBoot {
+    pub kernel: Vec<u8>,
+    pub ramdisk: Option<Vec<u8>>,
+    pub cmdline: String,
+}
+
+fn read_u32(image: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap())
+}
+
+// the cmdline fields are NUL-terminated unless they are completely filled
+fn read_string(image: &[u8], offset: usize, len: usize) -> String {
+    let field = &image[offset..offset + len];
+    let len = field.iter().position(|&byte| byte == 0).unwrap_or(len);
+    String::from_utf8_lossy(&field[..len]).into()
+}
+
+// returns the next section of the image which starts at a page boundary
+fn take_section<'a>(image: &'a [u8], offset: &mut usize, size: usize, page_size: usize, name: &str) -> SimpleResult<&'a [u8]> {
+    let Some(section) = offset.checked_add(size).and_then(|end| image.get(*offset..end)) else {
+        return simple_error!("The {name} at {:#x} ({size} bytes) is beyond the end of the image", *offset);
+    };
+
+    *offset += size.div_ceil(page_size) * page_size;
+    Ok(section)
+}
+
+pub fn is_boot_image(image: &[u8]) -> bool {
+    image.starts_with(BOOT_MAGIC)
+}
+
+impl<'a> BootImage<'a> {
+    pub fn parse(image: &'a [u8]) -> SimpleResult<BootImage<'a>> {
+        if !is_boot_image(image) || image.len() < 1632 {
+            return simple_error!("Not an Android boot image");
+        }
+
+        // the offset of header_version is the same in all versions (it was unused before v1)
+        let header_version = read_u32(image, 40);
+
+        match header_version {
+            0..=2 => BootImage::parse_v0(image, header_version),
+            3 | 4 => BootImage::parse_v3(image, header_version),
+            _ => simple_error!("Android boot image header version {header_version} is not supported"),
+        }
+    }
+
+    // the sections are kernel, ramdisk, second stage, recovery dtbo (v1) and dtb (v2) after the header page
+    fn parse_v0(image: &'a [u8], header_version: u32) -> SimpleResult<BootImage<'a>> {
+        let kernel_size = read_u32(image, 8) as usize;
+        let ramdisk_size = read_u32(image, 16) as usize;
+        let page_size = read_u32(image, 36) as usize;
+
+        if !page_size.is_power_of_two() || page_size < 2048 {
+            return simple_error!("Invalid page size {page_size} in the Android boot image");
+        }
+
+        // the extra args continue the cmdline without a separator
+        let mut cmdline = read_string(image, 64, BOOT_ARGS_SIZE);
+        cmdline.push_str(&read_string(image, 608, BOOT_EXTRA_ARGS_SIZE));
+
+        let mut offset = page_size;
+        let kernel = take_section(image, &mut offset, kernel_size, page_size, "kernel")?;
+        let ramdisk = take_section(image, &mut offset, ramdisk_size, page_size, "ramdisk")?;
+
+        Ok(BootImage { header_version, kernel, ramdisk, cmdline })
+    }
+
+    // the sections are kernel, ramdisk and the boot signature (v4) after the header page
+    fn parse_v3(image: &'a [u8], header_version: u32) -> SimpleResult<BootImage<'a>> {
+        let kernel_size = read_u32(image, 8) as usize;
+        let ramdisk_size = read_u32(image, 12) as usize;
+
+        let cmdline = read_string(image, 44, BOOT_ARGS_SIZE + BOOT_EXTRA_ARGS_SIZE);
+
+        let mut offset = BOOT_IMAGE_V3_PAGE_SIZE;
+        let kernel = take_section(image, &mut offset, kernel_size, BOOT_IMAGE_V3_PAGE_SIZE, "kernel")?;
+        let ramdisk = take_section(image, &mut offset, ramdisk_size, BOOT_IMAGE_V3_PAGE_SIZE, "ramdisk")?;
+
+        Ok(BootImage { header_version, kernel, ramdisk, cmdline })
+    }
+}
+
+impl<'a> VendorBootImage<'a> {
+    // the sections are the vendor ramdisks, dtb, vendor ramdisk table (v4) and bootconfig (v4) after the header
+    pub fn parse(image: &'a [u8]) -> SimpleResult<VendorBootImage<'a>> {
+        if !image.starts_with(VENDOR_BOOT_MAGIC) || image.len() < 2112 {
+            return simple_error!("Not an Android vendor_boot image");
+        }
+
+        let header_version = read_u32(image, 8);
+        if header_version != 3 && header_version != 4 {
+            return simple_error!("Android vendor_boot header version {header_version} is not supported");
+        }
+
+        if header_version == 4 && image.len() < 2128 {
+            return simple_error!("The Android vendor_boot v4 header is truncated");
+        }
+
+        let page_size = read_u32(image, 12) as usize;
+        let ramdisk_size = read_u32(image, 24) as usize;
+        let cmdline = read_string(image, 28, VENDOR_BOOT_ARGS_SIZE);
+        let header_size = read_u32(image, 2096) as usize;
+        let dtb_size = read_u32(image, 2100) as usize;
+
+        if !page_size.is_power_of_two() || page_size < 2048 {
+            return simple_error!("Invalid page size {page_size} in the Android vendor_boot image");
+        }
+
+        let mut offset = header_size.div_ceil(page_size) * page_size;
+
+        // all vendor ramdisks of the v4 table are stored back to back so they are loaded together like grub does
+        let ramdisk = take_section(image, &mut offset, ramdisk_size, page_size, "vendor ramdisk")?;
+        take_section(image, &mut offset, dtb_size, page_size, "dtb")?;
+
+        let mut bootconfig: &[u8] = &[];
+        if header_version == 4 {
+            let table_size = read_u32(image, 2112) as usize;
+            let bootconfig_size = read_u32(image, 2124) as usize;
+
+            take_section(image, &mut offset, table_size, page_size, "vendor ramdisk table")?;
+            bootconfig = take_section(image, &mut offset, bootconfig_size, page_size, "bootconfig")?;
+        }
+
+        Ok(VendorBootImage { header_version, ramdisk, cmdline, bootconfig })
+    }
+}
+
+// pads the ramdisk to 4 bytes so the next (possibly compressed) cpio archive is found by the kernel
+fn append_aligned(ramdisk: &mut Vec<u8>, data: &[u8]) {
+    ramdisk.resize(ramdisk.len().next_multiple_of(4), 0);
+    ramdisk.extend_from_slice(data);
+}
+
+// [ramdisk][bootconfig][padding][size][checksum][#BOOTCONFIG\n]
+fn append_bootconfig(ramdisk: &mut Vec<u8>, bootconfig: &[u8]) {
+    let padded_size = bootconfig.len().next_multiple_of(4);
+    let checksum = bootconfig.iter().fold(0u32, |sum, &byte| sum.wrapping_add(byte as u32));
+
+    ramdisk.extend_from_slice(bootconfig);
+    ramdisk.resize(ramdisk.len() + padded_size - bootconfig.len(), 0);
+    ramdisk.extend_from_slice(&(padded_size as u32).to_le_bytes());
+    ramdisk.extend_from_slice(&checksum.to_le_bytes());
+    ramdisk.extend_from_slice(BOOTCONFIG_MAGIC);
+}
+
+fn append_cmdline(cmdline: &mut String, part: &str) {
+    let part = part.trim();
+    if part.is_empty() {
+        return;
+    }
+
+    if !cmdline.is_empty() {
+        cmdline.push(' ');
+    }
+    cmdline.push_str(part);
+}
+
+impl AndroidBoot {
+    // The command line is vendor_boot's, then boot.img's and then the extra arguments of the user, so the user can
+    // override everything. The vendor ramdisk comes before the generic one like the Android bootloader does it.
+    pub fn new(boot_image: &[u8], vendor_boot_image: Option<&[u8]>, extra_cmdline: &str) -> SimpleResult<AndroidBoot> {
+        let boot = BootImage::parse(boot_image)?;
+        let vendor_boot = vendor_boot_image.map(VendorBootImage::parse).transpose()?;
+
+        if boot.kernel.is_empty() {
+            return simple_error!("The Android boot image does not contain a kernel");
+        }
+
+        let mut cmdline = String::new();
+        let mut ramdisk = Vec::new();
+
+        if let Some(vendor_boot) = &vendor_boot {
+            if boot.header_version < 3 {
+                return simple_error!("vendor_boot images can only be used with boot image header version 3 or newer");
+            }
+            if vendor_boot.header_version != boot.header_version {
+                return simple_error!("The vendor_boot header version {} does not match the boot image header version {}",
+                    vendor_boot.header_version, boot.header_version);
+            }
+
+            append_cmdline(&mut cmdline, &vendor_boot.cmdline);
+            ramdisk.extend_from_slice(vendor_boot.ramdisk);
+        }
+
+        append_cmdline(&mut cmdline, &boot.cmdline);
+        if !boot.ramdisk.is_empty() {
+            append_aligned(&mut ramdisk, boot.ramdisk);
+        }
+
+        if let Some(vendor_boot) = &vendor_boot {
+            if !vendor_boot.bootconfig.is_empty() {
+                append_bootconfig(&mut ramdisk, vendor_boot.bootconfig);
+                append_cmdline(&mut cmdline, "bootconfig");
+            }
+        }
+
+        append_cmdline(&mut cmdline, extra_cmdline);
+
+        Ok(AndroidBoot {
+            kernel: boot.kernel.to_vec(),
+            ramdisk: (!ramdisk.is_empty()).then_some(ramdisk),
+            cmdline,
+        })
+    }
+}
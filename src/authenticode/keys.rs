@@ -0,0 +1,87 @@
+// This file collects the certificates and hashes that Authenticode signatures are checked against: certificates
+// compiled into bs2boot and the Secure Boot databases db (allowed) and dbx (forbidden).
+// https://uefi.org/specs/UEFI/2.10/32_Secure_Boot_and_Driver_Signing.html#signature-database
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use der::Decode;
+use uefi::runtime::{self, VariableVendor};
+use uefi::{cstr16, guid, CStr16, Guid};
+use x509_cert::Certificate;
+
+const EFI_CERT_X509_GUID: Guid = guid!("a5c059a1-94e4-4aa7-87b5-ab155c2bf072");
+const EFI_CERT_SHA256_GUID: Guid = guid!("c1c41626-504c-4092-aca9-41f936934328");
+
+// DER encoded certificates that are trusted in addition to db, e.g.
+// include_bytes!("../../keys/kernel-signing-ca.der")
+pub const EMBEDDED_CERTIFICATES: &[&[u8]] = &[];
+
+pub struct TrustStore {
+    pub certificates: Vec<(Certificate, &'static str)>,     // with where they come from
+    pub hashes: Vec<Vec<u8>>,
+    pub forbidden_certificates: Vec<Certificate>,
+    pub forbidden_hashes: Vec<Vec<u8>>,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+// Parses a list of EFI_SIGNATURE_LISTs. Every signature starts with the GUID of its owner, unknown signature
+// types (e.g. SHA-1 or RSA-2048 keys) are skipped.
+fn parse_signature_lists(mut data: &[u8], certificates: &mut Vec<Certificate>, hashes: &mut Vec<Vec<u8>>) {
+    while data.len() >= 28 {
+        let signature_type = Guid::from_bytes(data[..16].try_into().unwrap());
+        let list_size = read_u32(data, 16);
+        let header_size = read_u32(data, 20);
+        let signature_size = read_u32(data, 24);
+
+        let Some(list) = data.get(28 + header_size..list_size) else {
+            return;
+        };
+
+        if signature_size > 16 {
+            for signature in list.chunks_exact(signature_size) {
+                let signature_data = &signature[16..];
+
+                if signature_type == EFI_CERT_X509_GUID {
+                    if let Ok(certificate) = Certificate::from_der(signature_data) {
+                        certificates.push(certificate);
+                    }
+                } else if signature_type == EFI_CERT_SHA256_GUID {
+                    hashes.push(signature_data.to_vec());
+                }
+            }
+        }
+
+        data = &data[list_size..];
+    }
+}
+
+fn read_database(name: &CStr16, certificates: &mut Vec<Certificate>, hashes: &mut Vec<Vec<u8>>) {
+    if let Ok((data, _)) = runtime::get_variable_boxed(name, &VariableVendor::IMAGE_SECURITY_DATABASE) {
+        parse_signature_lists(&data, certificates, hashes);
+    }
+}
+
+impl TrustStore {
+    pub fn load() -> TrustStore {
+        let mut certificates: Vec<(Certificate, &'static str)> = EMBEDDED_CERTIFICATES
+            .iter()
+            .filter_map(|der| Certificate::from_der(der).ok())
+            .map(|certificate| (certificate, "bs2boot"))
+            .collect();
+
+        let mut db_certificates = Vec::new();
+        let mut hashes = Vec::new();
+        read_database(cstr16!("db"), &mut db_certificates, &mut hashes);
+        certificates.extend(db_certificates.into_iter().map(|certificate| (certificate, "db")));
+
+        let mut forbidden_certificates = Vec::new();
+        let mut forbidden_hashes = Vec::new();
+        read_database(cstr16!("dbx"), &mut forbidden_certificates, &mut forbidden_hashes);
+
+        TrustStore { certificates, hashes, forbidden_certificates, forbidden_hashes }
+    }
+}
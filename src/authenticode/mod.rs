@@ -0,0 +1,127 @@
+/*
+This file verifies Authenticode signatures of PE images (EFI applications and kernels with EFI stub) without shim.
+The Authenticode hash of the image has to match the signed hash and the signer's certificate chain has to lead to a
+certificate in the trust store (see keys.rs). Images whose hash is in db are trusted without a signature, hashes and
+certificates in dbx are always refused. Like the firmware we do not check the validity period of certificates
+because there is no trustworthy clock at boot time.
+*/
+
+extern crate alloc;
+
+mod keys;
+mod pe;
+mod pkcs7;
+
+use alloc::string::{String, ToString};
+use uefi::println;
+use x509_cert::Certificate;
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+use self::keys::TrustStore;
+use self::pe::SignedImage;
+use self::pkcs7::{verify_certificate_signature, Signature};
+
+// intermediate certificates in a chain
+const MAX_CHAIN_LENGTH: usize = 8;
+
+fn subject(certificate: &Certificate) -> String {
+    certificate.tbs_certificate.subject.to_string()
+}
+
+// returns where the trusted certificate that issued (or is) this certificate comes from
+fn trusted_issuer(store: &TrustStore, certificate: &Certificate) -> Option<&'static str> {
+    store.certificates.iter().find_map(|(trusted, source)| {
+        let issued_by_trusted = trusted.tbs_certificate.subject == certificate.tbs_certificate.issuer
+            && verify_certificate_signature(certificate, trusted).is_ok();
+
+        (trusted == certificate || issued_by_trusted).then_some(*source)
+    })
+}
+
+fn verify_signature(der: &[u8], digest: &[u8], store: &TrustStore, verbose: bool) -> SimpleResult<()> {
+    let signature = Signature::parse(der)?;
+
+    if signature.image_digest()? != digest {
+        return simple_error!("The signed hash does not match the image (modified after signing?)");
+    }
+
+    let mut certificate = signature.verify_signer()?;
+
+    for depth in 0..=MAX_CHAIN_LENGTH {
+        if verbose {
+            println!("  {depth}: {}", subject(certificate));
+        }
+
+        if store.forbidden_certificates.contains(certificate) {
+            return simple_error!("The certificate {} is forbidden by dbx", subject(certificate));
+        }
+
+        if let Some(source) = trusted_issuer(store, certificate) {
+            if verbose {
+                println!("  issued by a trusted certificate from {source}");
+            }
+            return Ok(());
+        }
+
+        let issuer = signature.certificates().find(|issuer| {
+            issuer.tbs_certificate.subject == certificate.tbs_certificate.issuer && *issuer != certificate
+        });
+
+        let Some(issuer) = issuer else {
+            return simple_error!("No trusted certificate found for {}", certificate.tbs_certificate.issuer);
+        };
+
+        verify_certificate_signature(certificate, issuer)?;
+        certificate = issuer;
+    }
+
+    simple_error!("The certificate chain is too long")
+}
+
+pub fn has_embedded_certificates() -> bool {
+    !keys::EMBEDDED_CERTIFICATES.is_empty()
+}
+
+// Returns an error if the image is not signed by a trusted certificate. With verbose the hash, the signer chains
+// and the reasons why signatures were not accepted are printed.
+pub fn verify(image: &[u8], verbose: bool) -> SimpleResult<()> {
+    let signed_image = SignedImage::parse(image)?;
+    let store = TrustStore::load();
+
+    if verbose {
+        println!("Authenticode SHA-256: {}", crate::manifest::to_hex(&signed_image.digest));
+        println!("Trust store: {} certificates, {} hashes", store.certificates.len(), store.hashes.len());
+    }
+
+    if store.forbidden_hashes.iter().any(|hash| hash[..] == signed_image.digest) {
+        return simple_error!("The image hash is forbidden by dbx");
+    }
+
+    if store.hashes.iter().any(|hash| hash[..] == signed_image.digest) {
+        if verbose {
+            println!("The image hash is in db");
+        }
+        return Ok(());
+    }
+
+    if signed_image.signatures.is_empty() {
+        return simple_error!("The image is not signed");
+    }
+
+    let mut result = simple_error!("The image is not signed");
+
+    for (idx, der) in signed_image.signatures.iter().enumerate() {
+        if verbose {
+            println!("Signature {idx}:");
+        }
+
+        result = verify_signature(der, &signed_image.digest, &store, verbose);
+        match &result {
+            Ok(()) => break,
+            Err(err) if verbose => println!("  {err}"),
+            Err(_) => {},
+        }
+    }
+    result
+}
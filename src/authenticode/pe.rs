@@ -0,0 +1,138 @@
+// This file computes the Authenticode hash of a PE/COFF image and finds its signatures in the certificate table.
+// https://learn.microsoft.com/en-us/windows/win32/debug/pe-format#process-for-generating-the-authenticode-pe-image-hash
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use sha2::{Digest, Sha256};
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+const IMAGE_DIRECTORY_ENTRY_SECURITY: usize = 4;
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 0x0002;
+
+pub struct SignedImage<'a> {
+    pub digest: [u8; 32],
+    pub signatures: Vec<&'a [u8]>,      // DER encoded PKCS#7 SignedData
+}
+
+fn read_u16(image: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(image.get(offset..offset + 2)?.try_into().unwrap()))
+}
+
+fn read_u32(image: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(image.get(offset..offset + 4)?.try_into().unwrap()))
+}
+
+impl<'a> SignedImage<'a> {
+    pub fn parse(image: &'a [u8]) -> SimpleResult<SignedImage<'a>> {
+        match SignedImage::parse_headers(image) {
+            Some(result) => result,
+            None => simple_error!("The PE headers are truncated"),
+        }
+    }
+
+    // returns None if a field is outside of the image
+    fn parse_headers(image: &'a [u8]) -> Option<SimpleResult<SignedImage<'a>>> {
+        if !image.starts_with(b"MZ") {
+            return Some(simple_error!("Not a PE image"));
+        }
+
+        let pe_offset = read_u32(image, 0x3c)? as usize;
+        if image.get(pe_offset..pe_offset + 4)? != b"PE\0\0" {
+            return Some(simple_error!("Not a PE image"));
+        }
+
+        let section_count = read_u16(image, pe_offset + 6)? as usize;
+        let optional_header_size = read_u16(image, pe_offset + 20)? as usize;
+        let optional_header = pe_offset + 24;
+
+        // the data directories start later in PE32+ because ImageBase and the stack/heap sizes are 64 bit
+        let (rva_count_offset, data_directories) = match read_u16(image, optional_header)? {
+            0x10b => (optional_header + 92, optional_header + 96),
+            0x20b => (optional_header + 108, optional_header + 112),
+            magic => return Some(simple_error!("Unknown PE optional header magic {magic:#x}")),
+        };
+
+        let checksum = optional_header + 64;
+        let headers_size = read_u32(image, optional_header + 60)? as usize;
+        let rva_count = read_u32(image, rva_count_offset)? as usize;
+
+        if rva_count <= IMAGE_DIRECTORY_ENTRY_SECURITY {
+            return Some(simple_error!("The image has no security directory"));
+        }
+
+        let security_directory = data_directories + IMAGE_DIRECTORY_ENTRY_SECURITY * 8;
+        let cert_table_offset = read_u32(image, security_directory)? as usize;
+        let cert_table_size = read_u32(image, security_directory + 4)? as usize;
+
+        if headers_size > image.len() || security_directory + 8 > headers_size {
+            return Some(simple_error!("Invalid PE header size {headers_size:#x}"));
+        }
+        if cert_table_size > 0 && cert_table_offset.checked_add(cert_table_size)? != image.len() {
+            return Some(simple_error!("The certificate table is not at the end of the image"));
+        }
+
+        // the headers without the checksum and the security directory entry
+        let mut hasher = Sha256::new();
+        hasher.update(&image[..checksum]);
+        hasher.update(&image[checksum + 4..security_directory]);
+        hasher.update(&image[security_directory + 8..headers_size]);
+
+        // then the sections in the order of their file offsets
+        let section_table = optional_header + optional_header_size;
+        let mut sections = Vec::new();
+        for idx in 0..section_count {
+            let section = section_table + idx * 40;
+            let raw_size = read_u32(image, section + 16)? as usize;
+            let raw_offset = read_u32(image, section + 20)? as usize;
+
+            if raw_size > 0 {
+                sections.push((raw_offset, raw_size));
+            }
+        }
+        sections.sort();
+
+        let mut hashed_size = headers_size;
+        for (raw_offset, raw_size) in sections {
+            hasher.update(image.get(raw_offset..raw_offset + raw_size)?);
+            hashed_size += raw_size;
+        }
+
+        // and everything after the sections except the certificate table
+        let data_end = image.len() - cert_table_size;
+        if hashed_size < data_end {
+            hasher.update(&image[hashed_size..data_end]);
+        }
+
+        // the offset has no meaning if the table is empty
+        let cert_table = if cert_table_size == 0 { &image[..0] } else { &image[cert_table_offset..] };
+        let signatures = match SignedImage::signatures(cert_table) {
+            Ok(signatures) => signatures,
+            Err(err) => return Some(Err(err)),
+        };
+
+        Some(Ok(SignedImage { digest: hasher.finalize().into(), signatures }))
+    }
+
+    // the certificate table is a list of 8 byte aligned WIN_CERTIFICATE structures
+    fn signatures(mut cert_table: &'a [u8]) -> SimpleResult<Vec<&'a [u8]>> {
+        let mut signatures = Vec::new();
+
+        while cert_table.len() >= 8 {
+            let length = read_u32(cert_table, 0).unwrap() as usize;
+            let cert_type = read_u16(cert_table, 6).unwrap();
+
+            let Some(certificate) = cert_table.get(8..length) else {
+                return simple_error!("Invalid WIN_CERTIFICATE length {length}");
+            };
+
+            if cert_type == WIN_CERT_TYPE_PKCS_SIGNED_DATA {
+                signatures.push(certificate);
+            }
+
+            cert_table = cert_table.get(length.next_multiple_of(8)..).unwrap_or(&[]);
+        }
+        Ok(signatures)
+    }
+}
@@ -0,0 +1,184 @@
+// This file parses and verifies the PKCS#7 SignedData of an Authenticode signature. Only RSA signatures with
+// SHA-256 digests are supported, which is what sbsign, pesign and Microsoft's UEFI CA produce.
+// https://download.microsoft.com/download/9/c/5/9c5b2167-8017-4bae-9fde-d599bac8184a/Authenticode_PE.docx
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use cms::cert::CertificateChoices;
+use cms::content_info::ContentInfo;
+use cms::signed_data::{SignedData, SignerIdentifier, SignerInfo};
+use der::asn1::{ObjectIdentifier, OctetString};
+use der::{Any, Decode, Encode, Sequence};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use x509_cert::spki::AlgorithmIdentifierOwned;
+use x509_cert::Certificate;
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+const OID_SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+const OID_SPC_INDIRECT_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.2.1.4");
+const OID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const OID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const OID_RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const OID_SHA256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const OID_SHA384_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.12");
+const OID_SHA512_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.13");
+
+#[derive(Sequence)]
+struct DigestInfo {
+    digest_algorithm: AlgorithmIdentifierOwned,
+    digest: OctetString,
+}
+
+// the content that is signed, it contains the Authenticode hash of the image
+#[derive(Sequence)]
+struct SpcIndirectDataContent {
+    data: Any,
+    message_digest: DigestInfo,
+}
+
+pub struct Signature {
+    signed_data: SignedData,
+}
+
+fn rsa_verify(certificate: &Certificate, scheme: Pkcs1v15Sign, hashed: &[u8], signature: &[u8]) -> SimpleResult<()> {
+    let spki = &certificate.tbs_certificate.subject_public_key_info;
+    if spki.algorithm.oid != OID_RSA_ENCRYPTION {
+        return simple_error!("Unsupported public key algorithm {}", spki.algorithm.oid);
+    }
+
+    let key = match RsaPublicKey::from_pkcs1_der(spki.subject_public_key.raw_bytes()) {
+        Ok(key) => key,
+        Err(err) => return simple_error!("Invalid RSA public key: {err}"),
+    };
+
+    match key.verify(scheme, hashed, signature) {
+        Ok(()) => Ok(()),
+        Err(_) => simple_error!("The RSA signature does not match"),
+    }
+}
+
+// checks that issuer signed the certificate
+pub fn verify_certificate_signature(certificate: &Certificate, issuer: &Certificate) -> SimpleResult<()> {
+    let tbs = match certificate.tbs_certificate.to_der() {
+        Ok(tbs) => tbs,
+        Err(err) => return simple_error!("Could not encode certificate: {err}"),
+    };
+
+    let (scheme, hashed) = match certificate.signature_algorithm.oid {
+        OID_SHA256_WITH_RSA => (Pkcs1v15Sign::new::<Sha256>(), Sha256::digest(&tbs).to_vec()),
+        OID_SHA384_WITH_RSA => (Pkcs1v15Sign::new::<Sha384>(), Sha384::digest(&tbs).to_vec()),
+        OID_SHA512_WITH_RSA => (Pkcs1v15Sign::new::<Sha512>(), Sha512::digest(&tbs).to_vec()),
+        oid => return simple_error!("Unsupported certificate signature algorithm {oid}"),
+    };
+
+    rsa_verify(issuer, scheme, &hashed, certificate.signature.raw_bytes())
+}
+
+impl Signature {
+    pub fn parse(der: &[u8]) -> SimpleResult<Signature> {
+        let content_info = match ContentInfo::from_der(der) {
+            Ok(content_info) => content_info,
+            Err(err) => return simple_error!("Invalid PKCS#7 signature: {err}"),
+        };
+
+        if content_info.content_type != OID_SIGNED_DATA {
+            return simple_error!("The PKCS#7 signature is not SignedData");
+        }
+
+        match content_info.content.decode_as::<SignedData>() {
+            Ok(signed_data) => Ok(Signature { signed_data }),
+            Err(err) => simple_error!("Invalid PKCS#7 SignedData: {err}"),
+        }
+    }
+
+    fn content(&self) -> SimpleResult<&Any> {
+        let encap_content_info = &self.signed_data.encap_content_info;
+
+        match (&encap_content_info.econtent, encap_content_info.econtent_type) {
+            (Some(content), OID_SPC_INDIRECT_DATA) => Ok(content),
+            _ => simple_error!("The signature does not contain an Authenticode SpcIndirectDataContent"),
+        }
+    }
+
+    // the Authenticode hash of the image that was signed
+    pub fn image_digest(&self) -> SimpleResult<Vec<u8>> {
+        let content = match self.content()?.decode_as::<SpcIndirectDataContent>() {
+            Ok(content) => content,
+            Err(err) => return simple_error!("Invalid SpcIndirectDataContent: {err}"),
+        };
+
+        if content.message_digest.digest_algorithm.oid != OID_SHA256 {
+            return simple_error!("Unsupported Authenticode digest algorithm {}", content.message_digest.digest_algorithm.oid);
+        }
+        Ok(content.message_digest.digest.as_bytes().to_vec())
+    }
+
+    pub fn certificates(&self) -> impl Iterator<Item = &Certificate> {
+        self.signed_data.certificates.iter().flat_map(|set| set.0.iter()).filter_map(|choice| match choice {
+            CertificateChoices::Certificate(certificate) => Some(certificate),
+            _ => None,
+        })
+    }
+
+    fn find_signer_certificate(&self, signer_info: &SignerInfo) -> SimpleResult<&Certificate> {
+        let SignerIdentifier::IssuerAndSerialNumber(id) = &signer_info.sid else {
+            return simple_error!("Signers identified by their key identifier are not supported");
+        };
+
+        match self.certificates().find(|certificate| {
+            certificate.tbs_certificate.issuer == id.issuer && certificate.tbs_certificate.serial_number == id.serial_number
+        }) {
+            Some(certificate) => Ok(certificate),
+            None => simple_error!("The signer's certificate is not part of the signature"),
+        }
+    }
+
+    // Checks that the signed attributes contain the digest of the content and that the signer signed them.
+    // Returns the certificate of the signer.
+    pub fn verify_signer(&self) -> SimpleResult<&Certificate> {
+        let Some(signer_info) = self.signed_data.signer_infos.0.iter().next() else {
+            return simple_error!("The signature has no signer");
+        };
+
+        if signer_info.digest_alg.oid != OID_SHA256 {
+            return simple_error!("Unsupported signer digest algorithm {}", signer_info.digest_alg.oid);
+        }
+
+        let Some(signed_attrs) = &signer_info.signed_attrs else {
+            return simple_error!("The signature has no signed attributes");
+        };
+
+        // PKCS#7 hashes the content octets of the SpcIndirectDataContent without its tag and length
+        let content_digest = Sha256::digest(self.content()?.value());
+
+        let message_digest = signed_attrs
+            .iter()
+            .find(|attribute| attribute.oid == OID_MESSAGE_DIGEST)
+            .and_then(|attribute| attribute.values.iter().next())
+            .and_then(|value| value.decode_as::<OctetString>().ok());
+
+        if message_digest.as_ref().map(OctetString::as_bytes) != Some(content_digest.as_slice()) {
+            return simple_error!("The signed message digest does not match the signed content");
+        }
+
+        let certificate = self.find_signer_certificate(signer_info)?;
+
+        // the signature is over the DER encoding of the attributes as SET OF instead of [0] IMPLICIT
+        let attrs = match signed_attrs.to_der() {
+            Ok(attrs) => attrs,
+            Err(err) => return simple_error!("Could not encode the signed attributes: {err}"),
+        };
+
+        let signature_algorithm = signer_info.signature_algorithm.oid;
+        if signature_algorithm != OID_RSA_ENCRYPTION && signature_algorithm != OID_SHA256_WITH_RSA {
+            return simple_error!("Unsupported signature algorithm {signature_algorithm}");
+        }
+
+        rsa_verify(certificate, Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(&attrs), signer_info.signature.as_bytes())?;
+        Ok(certificate)
+    }
+}
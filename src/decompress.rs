@@ -0,0 +1,264 @@
+// This file contains helpers to decompress images (e.g. xen.gz) before loading them and to check that the
+// compressed payload of a kernel is intact.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+// gzip header flags (https://www.rfc-editor.org/rfc/rfc1952#section-2.3.1)
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+// skips a NUL-terminated string
+fn skip_string(data: &[u8], offset: usize) -> Option<usize> {
+    let len = data.get(offset..)?.iter().position(|&byte| byte == 0)?;
+    Some(offset + len + 1)
+}
+
+// returns the offset of the deflate stream
+fn parse_gzip_header(data: &[u8]) -> Option<usize> {
+    if !is_gzip(data) || data.len() < 18 || data[2] != 8 {    // 8 = deflate, the only method in use
+        return None;
+    }
+
+    let flags = data[3];
+    let mut offset = 10;
+
+    if flags & FEXTRA != 0 {
+        let extra_len = u16::from_le_bytes([*data.get(offset)?, *data.get(offset + 1)?]) as usize;
+        offset += 2 + extra_len;
+    }
+    if flags & FNAME != 0 {
+        offset = skip_string(data, offset)?;
+    }
+    if flags & FCOMMENT != 0 {
+        offset = skip_string(data, offset)?;
+    }
+    if flags & FHCRC != 0 {
+        offset += 2;
+    }
+
+    (offset + 8 <= data.len()).then_some(offset)
+}
+
+pub fn gunzip(data: &[u8]) -> SimpleResult<Vec<u8>> {
+    let Some(offset) = parse_gzip_header(data) else {
+        return simple_error!("Invalid gzip header");
+    };
+
+    let decompressed = match miniz_oxide::inflate::decompress_to_vec(&data[offset..data.len() - 8]) {
+        Ok(decompressed) => decompressed,
+        Err(err) => return simple_error!("Could not decompress gzip data: {:?}", err.status),
+    };
+
+    // the trailer has the size modulo 2^32
+    let isize = u32::from_le_bytes(data[data.len() - 4..].try_into().unwrap());
+    if decompressed.len() as u32 != isize {
+        return simple_error!("The decompressed size does not match the gzip trailer");
+    }
+
+    Ok(decompressed)
+}
+
+// reads until the end of the stream; the readers of lzma-rust2 and ruzstd have their own no_std Read traits
+macro_rules! read_to_end {
+    ($reader:expr, $name:literal) => {{
+        let mut decompressed = Vec::new();
+        let mut len = 0;
+
+        loop {
+            decompressed.resize(len + 1024 * 1024, 0);
+            match $reader.read(&mut decompressed[len..]) {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(err) => return simple_error!("Could not decompress {} data: {:?}", $name, err),
+            }
+        }
+
+        decompressed.truncate(len);
+        decompressed
+    }};
+}
+
+// xz multibyte integers (7 bits per byte, little endian)
+fn read_varint(data: &[u8], offset: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+
+    for shift in (0..63).step_by(7) {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// decodes one xz block starting at its header and returns the offset of the next one (if it is known)
+fn unxz_block(data: &[u8], offset: usize, check_size: usize, decompressed: &mut Vec<u8>) -> SimpleResult<Option<usize>> {
+    use lzma_rust2::Read;
+
+    const FILTER_BCJ_X86: u64 = 0x04;
+    const FILTER_LZMA2: u64 = 0x21;
+
+    let header_size = (data[offset] as usize + 1) * 4;
+    let Some(header) = data.get(offset..offset + header_size) else {
+        return simple_error!("The xz block header at {offset:#x} is truncated");
+    };
+
+    let flags = header[1];
+    let mut pos = 2;
+    let compressed_size = if flags & 0x40 != 0 { read_varint(header, &mut pos) } else { None };
+    if flags & 0x80 != 0 {
+        read_varint(header, &mut pos);
+    }
+
+    let mut bcj_start = None;
+    let mut dict_size = None;
+
+    for _ in 0..(flags & 0x03) + 1 {
+        let (Some(id), Some(props_size)) = (read_varint(header, &mut pos), read_varint(header, &mut pos)) else {
+            return simple_error!("The xz block header at {offset:#x} is invalid");
+        };
+        let Some(props) = header.get(pos..pos + props_size as usize) else {
+            return simple_error!("The xz block header at {offset:#x} is invalid");
+        };
+        pos += props_size as usize;
+
+        match (id, props) {
+            (FILTER_BCJ_X86, []) => bcj_start = Some(0),
+            (FILTER_BCJ_X86, &[a, b, c, d]) => bcj_start = Some(u32::from_le_bytes([a, b, c, d]) as usize),
+            (FILTER_LZMA2, &[props]) if props <= 40 => {
+                dict_size = Some(match props {
+                    40 => u32::MAX,
+                    props => (2 | (props as u32 & 1)) << (props / 2 + 11),
+                });
+            },
+            _ => return simple_error!("Unsupported xz filter {id:#x}"),
+        }
+    }
+
+    let Some(dict_size) = dict_size else {
+        return simple_error!("The xz block at {offset:#x} is not LZMA2 compressed");
+    };
+
+    let compressed = &data[offset + header_size..];
+    let block = match bcj_start {
+        Some(start_pos) => {
+            let mut reader = lzma_rust2::filter::bcj::BcjReader::new_x86(lzma_rust2::Lzma2Reader::new(compressed, dict_size, None), start_pos);
+            read_to_end!(reader, "xz")
+        },
+        None => {
+            let mut reader = lzma_rust2::Lzma2Reader::new(compressed, dict_size, None);
+            read_to_end!(reader, "xz")
+        },
+    };
+    decompressed.extend_from_slice(&block);
+
+    // the block is padded to 4 bytes and followed by its check value
+    Ok(compressed_size.map(|size| offset + header_size + (size as usize).next_multiple_of(4) + check_size))
+}
+
+// Only the filters the kernel uses (LZMA2 with an optional x86 BCJ filter) are supported. The check values are
+// not verified, a corrupt stream is noticed by the LZMA2 decoder or by the size appended to kernel payloads.
+// https://tukaani.org/xz/xz-file-format.txt
+pub fn unxz(data: &[u8]) -> SimpleResult<Vec<u8>> {
+    const STREAM_HEADER_SIZE: usize = 12;
+
+    if !data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) || data.len() < STREAM_HEADER_SIZE {
+        return simple_error!("Invalid xz header");
+    }
+
+    let check_size = match data[7] & 0x0f {
+        0 => 0,
+        check_type => 4 << ((check_type - 1) / 3),
+    };
+
+    let mut decompressed = Vec::new();
+    let mut offset = STREAM_HEADER_SIZE;
+
+    // a header size of 0 starts the index, blocks without a compressed size in their header are only written as
+    // the single block of a stream
+    while data.get(offset).is_some_and(|&size| size != 0) {
+        match unxz_block(data, offset, check_size, &mut decompressed)? {
+            Some(next_offset) => offset = next_offset,
+            None => break,
+        }
+    }
+
+    Ok(decompressed)
+}
+
+// the legacy .lzma format with a 13 byte header
+pub fn unlzma(data: &[u8]) -> SimpleResult<Vec<u8>> {
+    use lzma_rust2::Read;
+
+    let mut reader = match lzma_rust2::LzmaReader::new_mem_limit(data, u32::MAX, None) {
+        Ok(reader) => reader,
+        Err(err) => return simple_error!("Invalid lzma header: {err:?}"),
+    };
+    Ok(read_to_end!(reader, "lzma"))
+}
+
+pub fn unzstd(data: &[u8]) -> SimpleResult<Vec<u8>> {
+    use ruzstd::io::Read;
+
+    let mut reader = match ruzstd::decoding::StreamingDecoder::new(data) {
+        Ok(reader) => reader,
+        Err(err) => return simple_error!("Invalid zstd frame header: {err:?}"),
+    };
+    Ok(read_to_end!(reader, "zstd"))
+}
+
+// The legacy lz4 format (lz4 -l) that the kernel uses: a magic number followed by blocks of at most 8 MiB
+// that are each prefixed with their compressed size
+pub fn unlz4_legacy(data: &[u8]) -> SimpleResult<Vec<u8>> {
+    const LEGACY_MAGIC: [u8; 4] = [0x02, 0x21, 0x4c, 0x18];
+    const MAX_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+    let Some(mut data) = data.strip_prefix(&LEGACY_MAGIC) else {
+        return simple_error!("Invalid lz4 legacy header");
+    };
+
+    let mut decompressed = Vec::new();
+
+    while data.len() >= 4 {
+        let (size, rest) = data.split_at(4);
+        let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+
+        // lz4 -l writes the magic number again when files are concatenated
+        if size == u32::from_le_bytes(LEGACY_MAGIC) as usize {
+            data = rest;
+            continue;
+        }
+
+        let Some(block) = rest.get(..size) else {
+            return simple_error!("The lz4 block at {:#x} is truncated", decompressed.len());
+        };
+
+        let len = decompressed.len();
+        decompressed.resize(len + MAX_BLOCK_SIZE, 0);
+        match lz4_flex::block::decompress_into(block, &mut decompressed[len..]) {
+            Ok(block_len) => decompressed.truncate(len + block_len),
+            Err(err) => return simple_error!("Could not decompress lz4 data: {err}"),
+        }
+
+        data = &rest[size..];
+    }
+
+    if !data.is_empty() {
+        return simple_error!("Trailing data after the last lz4 block");
+    }
+
+    Ok(decompressed)
+}
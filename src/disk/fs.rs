@@ -9,6 +9,7 @@ partitions were "mounted" at the root with their names as directories.
 extern crate alloc;
 
 use core::fmt::Display;
+use core::mem::ManuallyDrop;
 
 use alloc::{vec::Vec, string::{String, ToString}};
 
@@ -89,7 +90,10 @@ pub enum FileType {
     Other,
 }
 
-// an absolute path beginning with the partition name
+// virtual directories containing the partitions by file system UUID/label and GPT partition GUID/name
+pub const BY_DIRECTORIES: [&str; 4] = ["by-uuid", "by-partuuid", "by-label", "by-partlabel"];
+
+// an absolute path beginning with the partition name (e.g. sda1 or by-uuid/<UUID>)
 #[derive(Debug, Clone)]
 pub struct FsPath {
     pub components: Vec<String>,
@@ -185,13 +189,36 @@ impl FsPath {
         self
     }
 
+    // the partition name is two components long for paths in the by-* directories
+    fn partition_name_len(&self) -> usize {
+        match self.components.first() {
+            Some(first) if BY_DIRECTORIES.contains(&first.as_str()) => 2,
+            _ => 1,
+        }
+    }
+
+    // None for / and the by-* directories themselves
+    pub fn partition_name(&self) -> Option<String> {
+        let len = self.partition_name_len();
+        self.components.get(..len).map(|components| components.join("/"))
+    }
+
+    // the by-* directory if the path is exactly that
+    pub fn by_directory(&self) -> Option<&str> {
+        match &self.components[..] {
+            [directory] if BY_DIRECTORIES.contains(&directory.as_str()) => Some(directory),
+            _ => None,
+        }
+    }
+
     fn _to_string(&self, with_partition_name: bool, separator: &str) -> String {
-        if self.components.is_empty() || (!with_partition_name && self.components.len() == 1) {
+        let start_from = if with_partition_name { 0 } else { self.partition_name_len() };
+
+        if self.components.len() <= start_from {
             return "/".to_string();
         }
 
         let mut out = String::new();
-        let start_from = if with_partition_name { 0 } else { 1 };
 
         for component in &self.components[start_from..] {
             out.push_str(separator);
@@ -360,6 +387,21 @@ impl Filesystem for Ext4 {
     }
 }
 
+// for the file systems of partitions, which never close their protocol (see DiskIoMediaIdPair)
+impl Filesystem for ManuallyDrop<ScopedProtocol<SimpleFileSystem>> {
+    fn format(&self) -> FsType {
+        FsType::Fat
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FileError> {
+        (**self).read_file(path)
+    }
+
+    fn read_directory(&mut self, path: &str) -> Result<Directory, FileError> {
+        (**self).read_directory(path)
+    }
+}
+
 // implementation for UEFI FAT API
 impl Filesystem for ScopedProtocol<SimpleFileSystem> {
     fn format(&self) -> FsType {
@@ -0,0 +1,195 @@
+// This file reads GUID partition tables through DiskIo so we know the names and types of partitions. If the
+// primary GPT is damaged (wrong CRC), the backup GPT at the end of the disk is used.
+// https://uefi.org/specs/UEFI/2.10/05_GUID_Partition_Table_Format.html
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use crc::{Crc, CRC_32_ISO_HDLC};
+use uefi::proto::media::disk::DiskIo;
+use uefi::{guid, println, Guid};
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+const SIGNATURE: &[u8] = b"EFI PART";
+const MIN_HEADER_SIZE: usize = 92;
+const MIN_ENTRY_SIZE: usize = 128;
+const MAX_ENTRY_SIZE: usize = 4096;
+const MAX_ENTRIES: usize = 1024;     // more than anyone uses, protects against absurd allocations
+
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+const ATTRIBUTE_REQUIRED: u64 = 1 << 0;
+const ATTRIBUTE_NO_BLOCK_IO: u64 = 1 << 1;
+const ATTRIBUTE_LEGACY_BIOS_BOOTABLE: u64 = 1 << 2;
+
+const KNOWN_TYPES: &[(Guid, &str)] = &[
+    (guid!("c12a7328-f81f-11d2-ba4b-00a0c93ec93b"), "EFI System"),
+    (guid!("21686148-6449-6e6f-744e-656564454649"), "BIOS boot"),
+    (guid!("ebd0a0a2-b9e5-4433-87c0-68b6b72699c7"), "Microsoft basic data"),
+    (guid!("e3c9e316-0b5c-4db8-817d-f92df00215ae"), "Microsoft reserved"),
+    (guid!("de94bba4-06d1-4d40-a16a-bfd50179d6ac"), "Windows recovery"),
+    (guid!("0fc63daf-8483-4772-8e79-3d69d8477de4"), "Linux filesystem"),
+    (guid!("4f68bce3-e8cd-4db1-96e7-fbcaf984b709"), "Linux root (x86-64)"),
+    (guid!("8484680c-9521-48c6-9c11-b0720656f69e"), "Linux /usr (x86-64)"),
+    (guid!("933ac7e1-2eb4-4f13-b844-0e14e2aef915"), "Linux /home"),
+    (guid!("4d21b016-b534-45c2-a9fb-5c16e091fd2d"), "Linux /var"),
+    (guid!("bc13c2ff-59e6-4262-a352-b275fd6f7172"), "Linux extended boot"),
+    (guid!("0657fd6d-a4ab-43c4-84e5-0933c84b4f4f"), "Linux swap"),
+    (guid!("e6d6d379-f507-44c2-a23c-238f2a3df928"), "Linux LVM"),
+    (guid!("a19d880f-05fc-4d3b-a006-743f0f84911e"), "Linux RAID"),
+    (guid!("ca7d7ccb-63ed-4c53-861c-1742536059cc"), "Linux LUKS"),
+    (guid!("fe3a2a5d-4f32-41a7-b725-accc3285a309"), "ChromeOS kernel"),
+];
+
+pub struct GptEntry {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+struct Header {
+    entries_lba: u64,
+    entry_count: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_guid(data: &[u8], offset: usize) -> Guid {
+    Guid::from_bytes(data[offset..offset + 16].try_into().unwrap())
+}
+
+impl GptEntry {
+    pub fn type_name(&self) -> Option<&'static str> {
+        KNOWN_TYPES.iter().find(|(guid, _)| *guid == self.type_guid).map(|(_, name)| *name)
+    }
+
+    fn parse(entry: &[u8]) -> Option<GptEntry> {
+        let type_guid = read_guid(entry, 0);
+        if type_guid == Guid::ZERO {
+            return None;    // unused entry
+        }
+
+        // the name is NUL-terminated UTF-16LE (72 bytes)
+        let name_units = entry[56..128].chunks(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).take_while(|unit| *unit != 0);
+        let name = char::decode_utf16(name_units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect();
+
+        Some(GptEntry {
+            type_guid,
+            unique_guid: read_guid(entry, 16),
+            first_lba: read_u64(entry, 32),
+            attributes: read_u64(entry, 48),
+            name,
+        })
+    }
+}
+
+impl fmt::Display for GptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.type_name() {
+            Some(type_name) => write!(f, "type: {type_name}")?,
+            None => write!(f, "type: {}", self.type_guid)?,
+        }
+
+        write!(f, "  name: '{}'  partuuid: {}", self.name, self.unique_guid)?;
+
+        if self.attributes != 0 {
+            write!(f, "  attributes: {:#x}", self.attributes)?;
+
+            for (bit, name) in [(ATTRIBUTE_REQUIRED, "required"), (ATTRIBUTE_NO_BLOCK_IO, "no-block-io"), (ATTRIBUTE_LEGACY_BIOS_BOOTABLE, "legacy-bootable")] {
+                if self.attributes & bit != 0 {
+                    write!(f, " {name}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read(disk_io: &DiskIo, media_id: u32, offset: u64, size: usize) -> SimpleResult<Vec<u8>> {
+    let mut buffer = alloc::vec![0u8; size];
+    match disk_io.read_disk(media_id, offset, &mut buffer) {
+        Ok(()) => Ok(buffer),
+        Err(err) => simple_error!("Could not read from disk: {err}"),
+    }
+}
+
+fn read_header(disk_io: &DiskIo, media_id: u32, block_size: u64, lba: u64) -> SimpleResult<Header> {
+    let Some(offset) = lba.checked_mul(block_size) else {
+        return simple_error!("LBA {lba} is beyond the end of the disk");
+    };
+    let block = read(disk_io, media_id, offset, block_size as usize)?;
+
+    if !block.starts_with(SIGNATURE) {
+        return simple_error!("No GPT signature at LBA {lba}");
+    }
+
+    let header_size = read_u32(&block, 12) as usize;
+    if header_size < MIN_HEADER_SIZE || header_size > block.len() {
+        return simple_error!("Invalid GPT header size {header_size}");
+    }
+
+    // the CRC is calculated with the CRC field set to 0
+    let mut header = block[..header_size].to_vec();
+    header[16..20].fill(0);
+    if CRC32.checksum(&header) != read_u32(&block, 16) {
+        return simple_error!("The GPT header at LBA {lba} has a wrong CRC");
+    }
+
+    if read_u64(&block, 24) != lba {
+        return simple_error!("The GPT header at LBA {lba} belongs to another LBA");
+    }
+
+    let entry_count = read_u32(&block, 80) as usize;
+    let entry_size = read_u32(&block, 84) as usize;
+    // the spec requires 128 * 2^n bytes
+    let valid_entry_size = entry_size <= MAX_ENTRY_SIZE && entry_size.is_multiple_of(MIN_ENTRY_SIZE)
+        && (entry_size / MIN_ENTRY_SIZE).is_power_of_two();
+    if entry_count > MAX_ENTRIES || !valid_entry_size {
+        return simple_error!("Invalid GPT entry array ({entry_count} entries of {entry_size} bytes)");
+    }
+
+    Ok(Header { entries_lba: read_u64(&block, 72), entry_count, entry_size, entries_crc: read_u32(&block, 88) })
+}
+
+fn read_table(disk_io: &DiskIo, media_id: u32, block_size: u64, lba: u64) -> SimpleResult<Vec<GptEntry>> {
+    let header = read_header(disk_io, media_id, block_size, lba)?;
+    let (Some(offset), Some(size)) = (header.entries_lba.checked_mul(block_size), header.entry_count.checked_mul(header.entry_size)) else {
+        return simple_error!("The GPT entry array of the header at LBA {lba} is out of range");
+    };
+    let entries = read(disk_io, media_id, offset, size)?;
+
+    if CRC32.checksum(&entries) != header.entries_crc {
+        return simple_error!("The GPT entry array of the header at LBA {lba} has a wrong CRC");
+    }
+
+    Ok(entries.chunks(header.entry_size).filter_map(GptEntry::parse).collect())
+}
+
+// Returns the used partition entries or an error if the disk has no valid GPT. last_lba is the last block of the
+// disk where the backup header is.
+pub fn read_gpt(disk_io: &DiskIo, media_id: u32, block_size: u64, last_lba: u64) -> SimpleResult<Vec<GptEntry>> {
+    let primary_err = match read_table(disk_io, media_id, block_size, 1) {
+        Ok(entries) => return Ok(entries),
+        Err(err) => err,
+    };
+
+    match read_table(disk_io, media_id, block_size, last_lba) {
+        Ok(entries) => {
+            println!("{primary_err}, using the backup GPT");
+            Ok(entries)
+        },
+        Err(_) => Err(primary_err),
+    }
+}
@@ -0,0 +1,58 @@
+// This file notices block devices that are plugged in while the shell is running. The firmware signals an event
+// whenever a BlockIO protocol is installed, the shell then rescans the devices while it waits for input. Removed
+// devices do not cause a notification, they are noticed when the devices are used the next time.
+
+extern crate alloc;
+
+use alloc::string::String;
+use core::ffi::c_void;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use uefi::boot::{self, EventType, Tpl};
+use uefi::proto::media::block::BlockIO;
+use uefi::{println, Event, Identify};
+
+use super::StorageDevice;
+
+static BLOCK_IO_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "efiapi" fn block_io_installed(_event: Event, _context: Option<NonNull<c_void>>) {
+    BLOCK_IO_INSTALLED.store(true, Ordering::Relaxed);
+}
+
+// the event has to be closed before bs2boot exits because it points to our notify function
+pub fn register_block_io_notify() -> Option<Event> {
+    let event = unsafe { boot::create_event(EventType::NOTIFY_SIGNAL, Tpl::CALLBACK, Some(block_io_installed), None) }.ok()?;
+
+    match boot::register_protocol_notify(&BlockIO::GUID, &event) {
+        Ok(_) => Some(event),
+        Err(_) => {
+            let _ = boot::close_event(event);
+            None
+        },
+    }
+}
+
+// true once after a BlockIO protocol was installed
+pub fn take_notification() -> bool {
+    BLOCK_IO_INSTALLED.swap(false, Ordering::Relaxed)
+}
+
+// Devices are compared by their device path because names can shift when a device is added or removed.
+// old contains the names and device paths of the devices before the rescan.
+pub fn report_changes(old: &[(String, String)], new: &[StorageDevice]) {
+    for (name, device_path) in old {
+        match new.iter().find(|device| device.device_path() == device_path) {
+            None => println!("Removed {name} ({device_path})"),
+            Some(device) if device.linux_name() != name => println!("{name} is now {}", device.linux_name()),
+            Some(_) => {},
+        }
+    }
+
+    for device in new {
+        if !old.iter().any(|(_, device_path)| device_path == device.device_path()) {
+            println!("Added {device}");
+        }
+    }
+}
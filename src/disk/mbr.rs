@@ -0,0 +1,140 @@
+// This file reads DOS partition tables (MBR) including logical partitions in the EBR chain of an extended partition.
+// Logical partitions are numbered from 5 in the order of the chain like Linux does, the firmware's numbering can
+// differ so partitions are matched to the firmware's handles by their start LBA.
+// https://en.wikipedia.org/wiki/Extended_boot_record
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::fmt;
+use uefi::proto::media::disk::DiskIo;
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+const TABLE_OFFSET: usize = 446;
+const ENTRY_SIZE: usize = 16;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+const TYPE_GPT_PROTECTIVE: u8 = 0xee;
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+
+const KNOWN_TYPES: &[(u8, &str)] = &[
+    (0x01, "FAT12"),
+    (0x04, "FAT16 <32M"),
+    (0x05, "Extended"),
+    (0x06, "FAT16"),
+    (0x07, "HPFS/NTFS/exFAT"),
+    (0x0b, "W95 FAT32"),
+    (0x0c, "W95 FAT32 (LBA)"),
+    (0x0e, "W95 FAT16 (LBA)"),
+    (0x0f, "W95 Extended (LBA)"),
+    (0x27, "Hidden NTFS WinRE"),
+    (0x82, "Linux swap"),
+    (0x83, "Linux"),
+    (0x85, "Linux extended"),
+    (0x8e, "Linux LVM"),
+    (0xa5, "FreeBSD"),
+    (0xa6, "OpenBSD"),
+    (0xa9, "NetBSD"),
+    (0xee, "GPT"),
+    (0xef, "EFI (FAT-12/16/32)"),
+    (0xfd, "Linux raid autodetect"),
+];
+
+pub struct MbrEntry {
+    pub number: u32,            // 1-4 for primary, 5+ for logical partitions
+    pub partition_type: u8,
+    pub first_lba: u64,
+    pub bootable: bool,
+}
+
+impl MbrEntry {
+    pub fn type_name(&self) -> Option<&'static str> {
+        KNOWN_TYPES.iter().find(|(id, _)| *id == self.partition_type).map(|(_, name)| *name)
+    }
+
+    // relative_to is the LBA that the start of the entry is relative to
+    fn parse(number: u32, entry: &[u8], relative_to: u64) -> Option<MbrEntry> {
+        let partition_type = entry[4];
+        let start = u32::from_le_bytes(entry[8..12].try_into().unwrap());
+        let sectors = u32::from_le_bytes(entry[12..16].try_into().unwrap());
+
+        if partition_type == 0 || sectors == 0 {
+            return None;    // unused entry
+        }
+
+        Some(MbrEntry { number, partition_type, first_lba: relative_to + start as u64, bootable: entry[0] == 0x80 })
+    }
+
+    fn is_extended(&self) -> bool {
+        EXTENDED_TYPES.contains(&self.partition_type)
+    }
+}
+
+impl fmt::Display for MbrEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "type: {:#04x} ({})", self.partition_type, self.type_name().unwrap_or("unknown"))?;
+
+        if self.bootable {
+            write!(f, "  bootable")?;
+        }
+        Ok(())
+    }
+}
+
+// the four entries of the partition table in the sector at lba
+fn read_table(disk_io: &DiskIo, media_id: u32, block_size: u64, lba: u64) -> SimpleResult<Vec<[u8; ENTRY_SIZE]>> {
+    let mut sector = alloc::vec![0u8; block_size as usize];
+    if let Err(err) = disk_io.read_disk(media_id, lba * block_size, &mut sector) {
+        return simple_error!("Could not read from disk: {err}");
+    }
+
+    if sector[510..512] != BOOT_SIGNATURE {
+        return simple_error!("No partition table at LBA {lba}");
+    }
+
+    Ok(sector[TABLE_OFFSET..TABLE_OFFSET + 4 * ENTRY_SIZE].chunks(ENTRY_SIZE).map(|entry| entry.try_into().unwrap()).collect())
+}
+
+// Returns the primary and logical partitions. Disks with a protective MBR (GPT) have no MBR partitions.
+pub fn read_mbr(disk_io: &DiskIo, media_id: u32, block_size: u64) -> SimpleResult<Vec<MbrEntry>> {
+    let primary: Vec<MbrEntry> = read_table(disk_io, media_id, block_size, 0)?
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, entry)| MbrEntry::parse(idx as u32 + 1, entry, 0))
+        .collect();
+
+    if primary.iter().any(|entry| entry.partition_type == TYPE_GPT_PROTECTIVE) {
+        return Ok(Vec::new());
+    }
+
+    let mut logical = Vec::new();
+
+    // every EBR has the logical partition relative to itself and a link to the next EBR relative to the extended
+    // partition in its first two entries
+    if let Some(extended) = primary.iter().find(|entry| entry.is_extended()) {
+        let mut visited = Vec::new();
+        let mut ebr_lba = extended.first_lba;
+
+        // EBRs without a logical partition do not get a number
+        while !visited.contains(&ebr_lba) && visited.len() < MAX_LOGICAL_PARTITIONS {
+            visited.push(ebr_lba);
+
+            let Ok(table) = read_table(disk_io, media_id, block_size, ebr_lba) else {
+                break;  // a broken chain ends here like in Linux
+            };
+
+            if let Some(entry) = MbrEntry::parse(5 + logical.len() as u32, &table[0], ebr_lba) {
+                logical.push(entry);
+            }
+
+            match MbrEntry::parse(0, &table[1], extended.first_lba) {
+                Some(next) if next.is_extended() => ebr_lba = next.first_lba,
+                _ => break,
+            }
+        }
+    }
+
+    Ok(primary.into_iter().chain(logical).collect())
+}
@@ -2,19 +2,25 @@
 
 extern crate alloc;
 
-use alloc::{boxed::Box, fmt, format, vec::Vec, string::String};
+use alloc::{boxed::Box, fmt, format, vec::Vec, string::{String, ToString}};
+use core::mem::ManuallyDrop;
 use uefi::proto::device_path::build::media::FilePath;
 use uefi::proto::device_path::build::DevicePathBuilder;
 use uefi::proto::device_path::text::{AllowShortcuts, DisplayOnly};
 use uefi::proto::device_path::{DevicePathNode, DeviceSubType, DeviceType};
 
+use crate::manifest::Manifest;
 use crate::simple_error::{simple_error, SimpleResult};
 use ext4_view::{Ext4, Ext4Read};
 use fs::{Filesystem, FsPath};
+use gpt::GptEntry;
+use mbr::MbrEntry;
+use volume_id::VolumeId;
 use uefi::boot::{self, OpenProtocolParams, ScopedProtocol};
+use uefi::proto::loaded_image::LoadedImage;
 use uefi::proto::media::disk::DiskIo;
 use uefi::proto::{media::block::BlockIO, ProtocolPointer};
-use uefi::CString16;
+use uefi::{CString16, Event};
 use uefi::{
     println,
     proto::{device_path::DevicePath, media::fs::SimpleFileSystem},
@@ -24,79 +30,195 @@ use uefi::{
 use crate::simple_error::{self, SimpleError};
 
 pub mod fs;
+pub mod gpt;
+mod hotplug;
+pub mod mbr;
+pub mod naming;
+pub mod volume_id;
 
 pub struct Storage {
     devices: Vec<StorageDevice>,
     last_seen_block_handles: Vec<Handle>,   // used to quickly check if we need to update the devices
+    manifest: SimpleResult<Option<Manifest>>,
+    block_io_event: Option<Event>,      // signaled when a block device is added
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        if let Some(event) = self.block_io_event.take() {
+            let _ = boot::close_event(event);
+        }
+    }
 }
 
 pub enum StorageDevice {
     Drive {
         linux_name: String,
         size: u64,
+        device_path: String,
         partitions: Vec<Partition>,
     },
     CdRom {
         linux_name: String,
         size: u64,
+        device_path: String,
     }
 }
 
 pub struct Partition {
     linux_name: String,
     handle: Handle,
+    device_path: String,    // to recognize a handle that the firmware reused for another device
     media_id: u32,
     size: u64,
     fs: Option<Box<dyn Filesystem>>,
+    gpt: Option<GptEntry>,
+    mbr: Option<MbrEntry>,
+    volume_id: VolumeId,
 }
 
+// The file systems of partitions keep their protocols without closing them: the handle disappears when the device is
+// removed, closing would fail then and uefi-rs panics. Protocols opened with GetProtocol do not need to be closed.
 struct DiskIoMediaIdPair {
-    disk_io: ScopedProtocol<DiskIo>,
+    disk_io: ManuallyDrop<ScopedProtocol<DiskIo>>,
     media_id: u32,
 }
 
 impl Storage {
     pub fn new() -> SimpleResult<Storage> {
         let block_handles = uefi::boot::find_handles::<BlockIO>().unwrap();
-        let devices = StorageDevice::from_block_handles(&block_handles)?;
+        let devices = StorageDevice::from_block_handles(&block_handles, &mut Vec::new())?;
         Ok(Storage {
             devices,
             last_seen_block_handles: block_handles,
+            manifest: Manifest::load(),
+            block_io_event: hotplug::register_block_io_notify(),
         })
     }
 
     pub fn devices(&mut self) -> SimpleResult<&mut Vec<StorageDevice>> {
+        self.rescan_if_changed()?;
+        Ok(self.devices.as_mut())
+    }
+
+    // Updates the devices if block devices were added or removed and prints what changed. Partitions that are
+    // still there keep their file system. Returns whether something changed.
+    fn rescan_if_changed(&mut self) -> SimpleResult<bool> {
         let block_handles = uefi::boot::find_handles::<BlockIO>().unwrap();
-        let devices_changed = self.last_seen_block_handles != block_handles;
+        if self.last_seen_block_handles == block_handles {
+            return Ok(false);
+        }
 
-        if devices_changed {
-            self.devices = StorageDevice::from_block_handles(&block_handles)?;
-            self.last_seen_block_handles = block_handles;
-            return simple_error!("The block devices have changed. The names of existing devices may have changed as well. Please check with 'ls'.")
+        let old_devices = core::mem::take(&mut self.devices);
+        let old_names: Vec<(String, String)> = old_devices
+            .iter()
+            .map(|device| (device.linux_name().to_string(), device.device_path().to_string()))
+            .collect();
+
+        let mut known_partitions: Vec<Partition> = old_devices
+            .into_iter()
+            .flat_map(|device| match device {
+                StorageDevice::Drive { partitions, .. } => partitions,
+                StorageDevice::CdRom { .. } => Vec::new(),
+            })
+            .collect();
+
+        // the partitions that are left over are gone, their file systems do not close their protocols
+        self.devices = StorageDevice::from_block_handles(&block_handles, &mut known_partitions)?;
+        self.last_seen_block_handles = block_handles;
+
+        hotplug::report_changes(&old_names, &self.devices);
+        Ok(true)
+    }
+
+    // called while waiting for input, returns true if a device was added and the changes were printed
+    pub fn poll_hotplug(&mut self) -> bool {
+        if !hotplug::take_notification() {
+            return false;
         }
 
-        Ok(self.devices.as_mut())
+        match self.rescan_if_changed() {
+            Ok(changed) => changed,
+            Err(err) => {
+                println!("Could not rescan the block devices: {err}");
+                true
+            },
+        }
     }
 
     pub fn read_file(&mut self, path: &FsPath) -> SimpleResult<Vec<u8>> {
-        let Some(partition_name) = path.components.first() else {
-            return simple_error!("/ is not a file.");
+        let Some(partition_name) = path.partition_name() else {
+            return simple_error!("{path} is not a file.");
         };
     
-        let partition = self.partition_by_name(partition_name)?;
+        let partition = self.partition_by_name(&partition_name)?;
     
         let Some(fs) = partition.fs() else {
             return simple_error!("The partition's filesystem could not be read.");
         };
     
-        match fs.read_file(&path.path_on_partition()) {
-            Err(fs::FileError::NotAFile) => simple_error!("{path} is not a file."),
-            Err(fs::FileError::NotFound) => simple_error!("{path} not found."),
-            Err(_) => simple_error!("An error occurred."),
-            Ok(data) => Ok(data),
+        let data = match fs.read_file(&path.path_on_partition()) {
+            Err(fs::FileError::NotAFile) => return simple_error!("{path} is not a file."),
+            Err(fs::FileError::NotFound) => return simple_error!("{path} not found."),
+            Err(_) => return simple_error!("An error occurred."),
+            Ok(data) => data,
+        };
+
+        self.check_manifest(path, Some(&data))?;
+        Ok(data)
+    }
+
+    // Fails if the manifest lists the file with another hash. data is None if the file could not be read, which is
+    // only an error if the manifest lists the file. Entries can address the partition under any of its names and
+    // paths on FAT are compared case-insensitively.
+    pub fn check_manifest(&mut self, path: &FsPath, data: Option<&[u8]>) -> SimpleResult<()> {
+        let Some(partition_name) = path.partition_name() else {
+            return Ok(());
+        };
+
+        self.rescan_if_changed()?;
+        let partition = find_partition(&mut self.devices, &partition_name)?;
+
+        let manifest = match &self.manifest {
+            Ok(Some(manifest)) => manifest,
+            Ok(None) => return Ok(()),
+            Err(err) => return simple_error!("{err}"),
+        };
+
+        let on_esp = Some(partition.handle) == esp_handle();
+        let ignore_case = on_esp || partition.fstype() == Some(fs::FsType::Fat);
+        let same_path = |a: &str, b: &str| if ignore_case { a.eq_ignore_ascii_case(b) } else { a == b };
+        let path_on_partition = path.path_on_partition();
+
+        let matches = |entry: &str| match FsPath::parse(entry) {
+            Ok(entry) => {
+                entry.partition_name().is_some_and(|name| partition.has_name(&name))
+                    && same_path(&entry.path_on_partition(), &path_on_partition)
+            },
+            Err(_) => on_esp && same_path(entry, path_on_partition.trim_start_matches('/')),  // relative to the ESP
+        };
+
+        match data {
+            Some(data) => manifest.check(&path.to_string(), data, matches),
+            None if manifest.lists(matches) => simple_error!("{path} is listed in {} but could not be read", crate::manifest::MANIFEST_PATH),
+            None => Ok(()),
         }
     }
 
+    // fails if a signed manifest is required but missing or invalid
+    pub fn manifest(&self) -> SimpleResult<Option<&Manifest>> {
+        match &self.manifest {
+            Ok(manifest) => Ok(manifest.as_ref()),
+            Err(err) => simple_error!("{err}"),
+        }
+    }
+
+    // the name of the partition this bootloader was loaded from
+    pub fn esp_partition_name(&mut self) -> SimpleResult<Option<String>> {
+        let esp = esp_handle();
+        Ok(self.partitions()?.into_iter().find(|partition| Some(partition.handle) == esp).map(|partition| partition.linux_name.clone()))
+    }
+
     pub fn partitions(&mut self) -> SimpleResult<Vec<&mut Partition>> {
         let mut partitions = Vec::new();
         for storage_device in self.devices()? {
@@ -110,19 +232,25 @@ impl Storage {
     }
 
     pub fn partition_by_name(&mut self, name: &str) -> SimpleResult<&mut Partition> {
-        for storage_device in self.devices()? {
-            let StorageDevice::Drive { partitions, .. } = storage_device else {
-                continue; // ignore CD drives
-            };
+        self.rescan_if_changed()?;
+        find_partition(&mut self.devices, name)
+    }
+}
 
-            for partition in partitions {
-                if partition.linux_name() == name {
-                    return Ok(partition);
-                }
+// does not rescan so the manifest can be borrowed at the same time
+fn find_partition<'a>(devices: &'a mut [StorageDevice], name: &str) -> SimpleResult<&'a mut Partition> {
+    for storage_device in devices {
+        let StorageDevice::Drive { partitions, .. } = storage_device else {
+            continue; // ignore CD drives
+        };
+
+        for partition in partitions {
+            if partition.has_name(name) {
+                return Ok(partition);
             }
         }
-        simple_error!("No partition with the name {name} was found.")
     }
+    simple_error!("No partition with the name {name} was found.")
 }
 
 impl StorageDevice {
@@ -133,6 +261,13 @@ impl StorageDevice {
         }
     }
 
+    pub fn device_path(&self) -> &str {
+        match self {
+            StorageDevice::Drive { device_path, .. } => device_path.as_str(),
+            StorageDevice::CdRom { device_path, .. } => device_path.as_str(),
+        }
+    }
+
     pub fn size(&self) -> u64 {
         match self {
             StorageDevice::Drive { size, .. } => *size,
@@ -140,14 +275,11 @@ impl StorageDevice {
         }
     }
 
-    pub fn from_block_handles(block_handles: &Vec<Handle>) -> SimpleResult<Vec<StorageDevice>> {
+    // known_partitions are reused (with their file system) if their handle, device path and media are still there
+    pub fn from_block_handles(block_handles: &Vec<Handle>, known_partitions: &mut Vec<Partition>) -> SimpleResult<Vec<StorageDevice>> {
         let mut drives: Vec<StorageDevice> = Vec::new();
         let handle_dpath_pairs: Vec<(Handle, Box<DevicePath>)> = get_device_paths_for_handles(block_handles.clone());
-        // TODO: sort block devices based on their DevicePaths; now we just assume that the drives will be found in the same order as linux finds (and names) them
-    
-        let mut sdx_devices = 0u8;  // devices that will be named like /dev/sdX under linux
-        let mut nvme_devices = 0u8; // named like /dev/nvmeXnY
-        let mut cd_devices = 0u8;   // named like /dev/srX
+        let mut namer = naming::Namer::default();
     
         // each group becomes one StorageDevice
         for group in group_block_devices(handle_dpath_pairs) {
@@ -158,35 +290,40 @@ impl StorageDevice {
                 continue;
             };
     
-            // determining linux_name
-            #[derive(Debug)]
-            enum DriveType {
-                Sdx,    // anything that will be named like /dev/sdX under linux
-                Nvme { namespace: u32 },
-                Cd,
-            }
-    
-            let drive_type = if let Ok(uefi::proto::device_path::DevicePathNodeEnum::MessagingNvmeNamespace(namespace)) = root_dpath.node_after_pci_node().unwrap().as_enum() {
-                DriveType::Nvme { namespace: namespace.namespace_identifier()}
-            } else if group.iter().any(|(_, dpath)| dpath.contains((DeviceType::MEDIA, DeviceSubType::MEDIA_CD_ROM))) {
-                DriveType::Cd
-            } else {
-                DriveType::Sdx
-            };
+            let is_cd = group.iter().any(|(_, dpath)| dpath.contains((DeviceType::MEDIA, DeviceSubType::MEDIA_CD_ROM)));
+            let drive_kind = naming::drive_kind(root_dpath, is_cd);
+            let controller = device_path_text(root_dpath, |node| node.device_type() == DeviceType::MESSAGING);
+            let linux_name = namer.name(&drive_kind, &controller);
+            let device_path = device_path_text(root_dpath, |_| false);
     
             let scoped_prot = open_protocol_unsafe::<BlockIO>(*root_handle).unwrap();
             let root_block_io = scoped_prot.get().unwrap();
             let root_media = root_block_io.media();
             let root_size = root_media.last_block() * (root_media.block_size() as u64); // is this correct?
     
-            if let DriveType::Cd = drive_type {
-                drives.push(StorageDevice::CdRom { linux_name: format!("sr{}", cd_devices), size: root_size });
-                cd_devices += 1;
+            if drive_kind == naming::DriveKind::Cd {
+                drives.push(StorageDevice::CdRom { linux_name, size: root_size, device_path });
                 // there are no partitions on CD drives; skip to next drive
                 // actually, in testing my CD hat multiple handles which might be important but for now let's just ignore that
                 continue;
             }
     
+            // the firmware does not tell us the names and types of GPT partitions
+            // (without DiskIo we only know what the firmware tells us)
+            let (mut gpt_entries, mut mbr_entries) = match open_protocol_unsafe::<DiskIo>(*root_handle) {
+                Ok(disk_io) => {
+                    let (media_id, block_size) = (root_media.media_id(), root_media.block_size() as u64);
+                    let gpt_entries = gpt::read_gpt(&disk_io, media_id, block_size, root_media.last_block()).unwrap_or_default();
+                    let mbr_entries = if gpt_entries.is_empty() {
+                        mbr::read_mbr(&disk_io, media_id, block_size).unwrap_or_default()
+                    } else {
+                        Vec::new()
+                    };
+                    (gpt_entries, mbr_entries)
+                },
+                Err(_) => (Vec::new(), Vec::new()),
+            };
+
             // collect all partitions before creating the drive
             let mut partitions = Vec::new();
     
@@ -198,36 +335,51 @@ impl StorageDevice {
                     let block_io = scoped_prot.get().unwrap();
                     let media = block_io.media();
 
-                    let partition = Partition::new(
-                        match drive_type {
-                            DriveType::Sdx => format!("sd{}{}", ('a' as u8 + sdx_devices) as char, harddrive.partition_number()),
-                            DriveType::Nvme { namespace } => format!("nvme{}n{}p{}", nvme_devices, namespace, harddrive.partition_number()),
-                            DriveType::Cd => unreachable!(),
+                    let gpt_entry = gpt_entries
+                        .iter()
+                        .position(|entry| entry.first_lba == harddrive.partition_start())
+                        .map(|idx| gpt_entries.swap_remove(idx));
+                    let mbr_entry = mbr_entries
+                        .iter()
+                        .position(|entry| entry.first_lba == harddrive.partition_start())
+                        .map(|idx| mbr_entries.swap_remove(idx));
+
+                    // the firmware numbers logical partitions differently than linux
+                    let number = mbr_entry.as_ref().map_or(harddrive.partition_number(), |entry| entry.number);
+
+                    let linux_partition_name = naming::partition_name(&linux_name, number);
+                    let partition_device_path = device_path_text(&dpath, |_| false);
+
+                    let known = known_partitions.iter().position(|known| {
+                        known.handle == handle && known.device_path == partition_device_path && known.media_id == media.media_id()
+                    });
+                    let partition = match known {
+                        Some(idx) => {
+                            let mut partition = known_partitions.swap_remove(idx);
+                            (partition.linux_name, partition.gpt, partition.mbr) = (linux_partition_name, gpt_entry, mbr_entry);
+                            partition
                         },
-                        handle,
-                        media.media_id(),
-                        media.last_block() * (media.block_size() as u64), // TODO: is this correct?
-                    );
+                        None => Partition::new(
+                            linux_partition_name,
+                            handle,
+                            partition_device_path,
+                            media.media_id(),
+                            media.last_block() * (media.block_size() as u64), // TODO: is this correct?
+                            gpt_entry,
+                            mbr_entry,
+                        ),
+                    };
 
                     partitions.push(partition);
                 }
             }
     
             drives.push(StorageDevice::Drive {
-                linux_name: match drive_type {
-                    DriveType::Sdx => format!("sd{}", ('a' as u8 + sdx_devices) as char),
-                    DriveType::Nvme { namespace } => format!("nvme{}n{}", nvme_devices, namespace),
-                    DriveType::Cd => unreachable!(),
-                },
+                linux_name,
                 size: root_size,
+                device_path,
                 partitions,
             });
-    
-            match drive_type {
-                DriveType::Sdx => sdx_devices += 1,
-                DriveType::Nvme { .. } => nvme_devices += 1,
-                DriveType::Cd => unreachable!(),
-            }
         }
 
         Ok(drives)
@@ -244,38 +396,51 @@ fn get_device_paths_for_handles(handles: Vec<Handle>) -> Vec<(Handle, Box<Device
     device_paths
 }
 
+// the text representation of the nodes before the first node for which stop returns true
+fn device_path_text(dpath: &DevicePath, stop: impl Fn(&DevicePathNode) -> bool) -> String {
+    let mut text = String::new();
+
+    for node in dpath.node_iter().take_while(|node| !stop(node)) {
+        if !text.is_empty() {
+            text.push('/');
+        }
+        match node.to_string(DisplayOnly(false), AllowShortcuts(false)) {
+            Ok(node_text) => text.push_str(&format!("{node_text}")),
+            Err(_) => text.push('?'),
+        }
+    }
+    text
+}
+
+type HandleDpathPair = (Handle, Box<DevicePath>);
+
 // helper for StorageDevice::from_block_handes()
+// Groups the handles by the device path of their disk (everything before the partition/CD node) and sorts the
+// groups in the order Linux names them.
 pub fn group_block_devices(handle_dpath_pairs: Vec<(Handle, Box<DevicePath>)>) -> Vec<Vec<(Handle, Box<DevicePath>)>> {
-    let mut groups: Vec<Vec<(Handle, Box<DevicePath>)>> = Vec::new();
-    let mut grouping_nodes: Vec<CString16> = Vec::new();
+    let mut groups: Vec<(String, Vec<HandleDpathPair>)> = Vec::new();
 
     for (handle, dpath) in handle_dpath_pairs {
-        let node= {
-            let node = dpath.node_after_pci_node().expect("A block device was not connected to a PCI device :(");
+        let disk = device_path_text(&dpath, |node| node.device_type() == DeviceType::MEDIA);
 
-            // DevicePathNode won't let me clone it so just convert it to a string :/
-            // this is a bit hacky because it assumes that all important data is contained in the string representation of the node
-            node.to_string(DisplayOnly(false), AllowShortcuts(false)).unwrap()
-        };
-        match grouping_nodes.iter().position(|n| *n == node) {
-            Some(idx) => groups[idx].push((handle, dpath)),
-            None => {
-                grouping_nodes.push(node);
-                groups.push(alloc::vec![(handle, dpath)]);
-            }
+        match groups.iter().position(|(group_disk, _)| *group_disk == disk) {
+            Some(idx) => groups[idx].1.push((handle, dpath)),
+            None => groups.push((disk, alloc::vec![(handle, dpath)])),
         }
     }
 
-    groups
+    groups.sort_by_cached_key(|(_, group)| naming::sort_key(&group[0].1));
+    groups.into_iter().map(|(_, group)| group).collect()
 }
 
 impl fmt::Display for StorageDevice {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{} size: {}",
+            "{}  {}  {}",
             self.linux_name(),
-            human_readable_size(self.size())
+            human_readable_size(self.size()),
+            self.device_path(),
         )
     }
 }
@@ -284,28 +449,38 @@ impl Partition {
     pub fn new(
         linux_name: String,
         handle: Handle,
+        device_path: String,
         media_id: u32,
         size: u64,
+        gpt: Option<GptEntry>,
+        mbr: Option<MbrEntry>,
     ) -> Self {
         let mut partition = Partition {
             linux_name,
             handle,
+            device_path,
             media_id,
             size,
             fs: None,
+            gpt,
+            mbr,
+            volume_id: VolumeId::default(),
         };
 
         partition.fs = partition.open_fs();
+        if let Ok(disk_io) = open_protocol_unsafe::<DiskIo>(handle) {
+            partition.volume_id = volume_id::read_volume_id(&disk_io, media_id);
+        }
         partition
     }
 
     pub fn open_fs(&self) -> Option<Box<dyn Filesystem>> {
         if let Ok(sfs) = open_protocol_unsafe::<SimpleFileSystem>(self.handle) {
-            return Some(Box::new(sfs));
+            return Some(Box::new(ManuallyDrop::new(sfs)));
         }
 
         let disk_io_media_id_pair = DiskIoMediaIdPair {
-            disk_io: open_protocol_unsafe::<DiskIo>(self.handle).unwrap(),
+            disk_io: ManuallyDrop::new(open_protocol_unsafe::<DiskIo>(self.handle).unwrap()),
             media_id: self.media_id,
         };
 
@@ -320,6 +495,46 @@ impl Partition {
         self.linux_name.as_str()
     }
 
+    // The name of a GPT partition, the file system UUID or label if any (None otherwise)
+    pub fn name_in(&self, by_directory: &str) -> Option<String> {
+        match by_directory {
+            "by-uuid" => self.volume_id.uuid.clone(),
+            "by-label" => self.volume_id.label.clone(),
+            "by-partuuid" => Some(self.gpt.as_ref()?.unique_guid.to_string()),
+            "by-partlabel" => Some(self.gpt.as_ref()?.name.clone()).filter(|name| !name.is_empty()),
+            _ => None,
+        }
+    }
+
+    // Besides the linux name, partitions can be addressed through the by-* directories (e.g. by-uuid/<UUID>) or like
+    // in the kernel command line (e.g. PARTUUID=<GUID>). UUIDs are case-insensitive.
+    pub fn has_name(&self, name: &str) -> bool {
+        if self.linux_name == name {
+            return true;
+        }
+
+        let (by_directory, value) = if let Some((key, value)) = name.split_once('=') {
+            let by_directory = match key {
+                "UUID" => "by-uuid",
+                "LABEL" => "by-label",
+                "PARTUUID" => "by-partuuid",
+                "PARTLABEL" => "by-partlabel",
+                _ => return false,
+            };
+            (by_directory, value)
+        } else if let Some((by_directory, value)) = name.split_once('/') {
+            (by_directory, value)
+        } else {
+            return false;
+        };
+
+        match self.name_in(by_directory) {
+            Some(own) if by_directory.ends_with("uuid") => own.eq_ignore_ascii_case(value),
+            Some(own) => own == value,
+            None => false,
+        }
+    }
+
     pub fn fstype(&self) -> Option<fs::FsType> {
         Some(self.fs.as_ref()?.format())
     }
@@ -376,7 +591,22 @@ impl fmt::Display for Partition {
             self.linux_name(),
             human_readable_size(self.size),
             self.fstype_as_str(),
-        )
+        )?;
+
+        if let Some(uuid) = &self.volume_id.uuid {
+            write!(f, "  uuid: {uuid}")?;
+        }
+        if let Some(label) = &self.volume_id.label {
+            write!(f, "  label: '{label}'")?;
+        }
+
+        if let Some(gpt) = &self.gpt {
+            write!(f, "\n      {gpt}")?;
+        }
+        if let Some(mbr) = &self.mbr {
+            write!(f, "\n      {mbr}")?;
+        }
+        Ok(())
     }
 }
 
@@ -419,6 +649,16 @@ where
     }
 }
 
+// the partition that this bootloader was loaded from
+pub fn esp_handle() -> Option<Handle> {
+    open_protocol_unsafe::<LoadedImage>(boot::image_handle()).ok()?.device()
+}
+
+// the file system that this bootloader was loaded from
+pub fn open_esp() -> Option<ScopedProtocol<SimpleFileSystem>> {
+    open_protocol_unsafe::<SimpleFileSystem>(esp_handle()?).ok()
+}
+
 
 impl Ext4Read for DiskIoMediaIdPair {
     fn read(
@@ -435,17 +675,12 @@ impl Ext4Read for DiskIoMediaIdPair {
 
 // implementing some simple helper functions for DevicePaths that are not in the UEFI crate :(
 trait DevicePathConvenience {
-    fn node_after_pci_node(&self) -> Option<&DevicePathNode>;
     fn is_partition(&self) -> bool;
     fn contains(&self, full_type: (DeviceType, DeviceSubType)) -> bool;
     fn get_node(&self, full_type: (DeviceType, DeviceSubType)) -> Option<&DevicePathNode>;
 }
 
 impl DevicePathConvenience for DevicePath {
-    fn node_after_pci_node(&self) -> Option<&DevicePathNode> {
-        self.node_iter().nth(2) // Assuming there will always be a PciRoot node followed by a PCI node; this was true for all devices I tested
-    }
-
     fn get_node(&self, full_type: (DeviceType, DeviceSubType)) -> Option<&DevicePathNode> {
         let mut iter = self.node_iter();
     
@@ -0,0 +1,165 @@
+/*
+This file names block devices like Linux does. Linux numbers disks in the order they are probed, which for the
+usual setups is the order of their PCI bus/device/function and then port/target/LUN. So devices are sorted by these
+numbers from their device paths before names are given out. The type of a device is also derived from its device
+path because the firmware does not tell us which driver Linux will use:
+- virtio-blk (vdX) disks have no messaging node, the BlockIO protocol is on a PCI device with a virtio-blk ID
+- Xen PV disks (xvdX) are children of the XenBus vendor node
+- SD/eMMC cards are mmcblkN, NVMe namespaces are nvmeXnY with X counting the controllers
+- everything else (SATA, SCSI, USB, ...) is sdX, CD drives are srN
+*/
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use uefi::boot;
+use uefi::proto::device_path::{DevicePath, DevicePathNodeEnum, DeviceType};
+use uefi::{guid, Guid};
+
+use crate::pci::PciIo;
+
+// XENBUS_PROTOCOL_GUID, used by OVMF's XenBusDxe for the nodes of its children
+const XENBUS_GUID: Guid = guid!("3d3ca290-b9a5-11e3-b75d-b8ac6f7d65e6");
+
+const VIRTIO_VENDOR_ID: u16 = 0x1af4;
+const VIRTIO_BLK_DEVICE_IDS: [u16; 2] = [0x1001, 0x1042];    // legacy and modern (virtio 1.0)
+
+#[derive(PartialEq)]
+pub enum DriveKind {
+    Sd,
+    Virtio,
+    Xen,
+    Mmc,
+    Nvme { namespace: u32 },
+    Cd,
+}
+
+// the numbers that determine the order in which Linux finds the device
+pub fn sort_key(dpath: &DevicePath) -> Vec<u64> {
+    let mut key = Vec::new();
+
+    for node in dpath.node_iter() {
+        match node.as_enum() {
+            Ok(DevicePathNodeEnum::AcpiAcpi(acpi)) => key.push(acpi.uid() as u64),
+            Ok(DevicePathNodeEnum::HardwarePci(pci)) => key.push(((pci.device() as u64) << 8) | pci.function() as u64),
+            Ok(DevicePathNodeEnum::MessagingAtapi(atapi)) => {
+                key.extend([atapi.primary_secondary().0 as u64, atapi.master_slave().0 as u64, atapi.logical_unit_number() as u64]);
+            },
+            Ok(DevicePathNodeEnum::MessagingSata(sata)) => {
+                key.extend([sata.hba_port_number() as u64, sata.port_multiplier_port_number() as u64, sata.logical_unit_number() as u64]);
+            },
+            Ok(DevicePathNodeEnum::MessagingScsi(scsi)) => key.extend([scsi.target_id() as u64, scsi.logical_unit_number() as u64]),
+            Ok(DevicePathNodeEnum::MessagingUsb(usb)) => key.extend([usb.parent_port_number() as u64, usb.interface() as u64]),
+            Ok(DevicePathNodeEnum::MessagingNvmeNamespace(namespace)) => key.push(namespace.namespace_identifier() as u64),
+            Ok(DevicePathNodeEnum::MessagingSd(sd)) => key.push(sd.slot_number() as u64),
+            Ok(DevicePathNodeEnum::MessagingEmmc(emmc)) => key.push(emmc.slot_number() as u64),
+            _ => {},
+        }
+    }
+    key
+}
+
+pub fn drive_kind(dpath: &DevicePath, is_cd: bool) -> DriveKind {
+    if is_cd {
+        return DriveKind::Cd;
+    }
+
+    let mut has_messaging_node = false;
+
+    for node in dpath.node_iter() {
+        match node.as_enum() {
+            Ok(DevicePathNodeEnum::MessagingNvmeNamespace(namespace)) => {
+                return DriveKind::Nvme { namespace: namespace.namespace_identifier() };
+            },
+            Ok(DevicePathNodeEnum::MessagingSd(_)) | Ok(DevicePathNodeEnum::MessagingEmmc(_)) => return DriveKind::Mmc,
+            Ok(DevicePathNodeEnum::HardwareVendor(vendor)) if vendor.vendor_guid() == XENBUS_GUID => return DriveKind::Xen,
+            _ => {},
+        }
+
+        has_messaging_node |= node.device_type() == DeviceType::MESSAGING;
+    }
+
+    // RAM disks, RAID controllers and the like also have no messaging node
+    if !has_messaging_node && is_virtio_blk(dpath) { DriveKind::Virtio } else { DriveKind::Sd }
+}
+
+// true if the BlockIO protocol is on the PCI device itself and that device is a virtio-blk device
+fn is_virtio_blk(dpath: &DevicePath) -> bool {
+    let mut remaining = dpath;
+    let Ok(handle) = boot::locate_device_path::<PciIo>(&mut remaining) else {
+        return false;
+    };
+
+    if remaining.node_iter().next().is_some() {
+        return false;   // there are more nodes below the PCI device
+    }
+
+    let Ok(pci_io) = crate::disk::open_protocol_unsafe::<PciIo>(handle) else {
+        return false;
+    };
+
+    matches!(pci_io.ids(), Some((VIRTIO_VENDOR_ID, device_id)) if VIRTIO_BLK_DEVICE_IDS.contains(&device_id))
+}
+
+// a, b, ..., z, aa, ab, ... like sd_format_disk_name in Linux
+fn disk_letters(mut idx: usize) -> String {
+    let mut letters = Vec::new();
+
+    loop {
+        letters.push(b'a' + (idx % 26) as u8);
+        if idx < 26 {
+            break;
+        }
+        idx = idx / 26 - 1;
+    }
+
+    letters.iter().rev().map(|letter| *letter as char).collect()
+}
+
+// hands out the names in the order of the (sorted) devices
+#[derive(Default)]
+pub struct Namer {
+    sd: usize,
+    virtio: usize,
+    xen: usize,
+    mmc: usize,
+    cd: usize,
+    nvme_controllers: Vec<String>,
+}
+
+impl Namer {
+    // controller is the device path of the controller (used to count NVMe controllers)
+    pub fn name(&mut self, kind: &DriveKind, controller: &str) -> String {
+        let next = |counter: &mut usize| {
+            *counter += 1;
+            *counter - 1
+        };
+
+        match kind {
+            DriveKind::Sd => format!("sd{}", disk_letters(next(&mut self.sd))),
+            DriveKind::Virtio => format!("vd{}", disk_letters(next(&mut self.virtio))),
+            DriveKind::Xen => format!("xvd{}", disk_letters(next(&mut self.xen))),
+            DriveKind::Mmc => format!("mmcblk{}", next(&mut self.mmc)),
+            DriveKind::Cd => format!("sr{}", next(&mut self.cd)),
+            DriveKind::Nvme { namespace } => {
+                let controller_idx = match self.nvme_controllers.iter().position(|known| known == controller) {
+                    Some(idx) => idx,
+                    None => {
+                        self.nvme_controllers.push(controller.into());
+                        self.nvme_controllers.len() - 1
+                    },
+                };
+                format!("nvme{controller_idx}n{namespace}")
+            },
+        }
+    }
+}
+
+// sda1, but nvme0n1p1 and mmcblk0p1 if the disk name ends with a digit
+pub fn partition_name(disk_name: &str, number: u32) -> String {
+    if disk_name.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{disk_name}p{number}")
+    } else {
+        format!("{disk_name}{number}")
+    }
+}
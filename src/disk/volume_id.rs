@@ -0,0 +1,65 @@
+// This file reads the UUID and label of a file system from its superblock the way blkid shows them, so partitions
+// can be addressed like /dev/disk/by-uuid and /dev/disk/by-label under Linux.
+
+extern crate alloc;
+
+use alloc::{format, string::String};
+use uefi::proto::media::disk::DiskIo;
+
+const EXT4_SUPERBLOCK: usize = 1024;
+const EXT4_MAGIC: u16 = 0xef53;
+
+#[derive(Default)]
+pub struct VolumeId {
+    pub uuid: Option<String>,
+    pub label: Option<String>,
+}
+
+// labels are padded with NULs (ext4) or spaces (FAT)
+fn parse_label(bytes: &[u8]) -> Option<String> {
+    let label = String::from_utf8_lossy(bytes);
+    let label = label.trim_end_matches(['\0', ' ']);
+
+    (!label.is_empty() && label != "NO NAME").then(|| label.into())
+}
+
+fn ext4(superblock: &[u8]) -> Option<VolumeId> {
+    if u16::from_le_bytes([superblock[0x38], superblock[0x39]]) != EXT4_MAGIC {
+        return None;
+    }
+
+    let uuid = &superblock[0x68..0x78];
+    let hex = |range: core::ops::Range<usize>| crate::manifest::to_hex(&uuid[range]);
+
+    Some(VolumeId {
+        uuid: Some(format!("{}-{}-{}-{}-{}", hex(0..4), hex(4..6), hex(6..8), hex(8..10), hex(10..16))),
+        label: parse_label(&superblock[0x78..0x88]),
+    })
+}
+
+// the volume serial number is shown as XXXX-XXXX
+fn fat(boot_sector: &[u8]) -> Option<VolumeId> {
+    let (serial_offset, label_offset) = if &boot_sector[0x52..0x57] == b"FAT32" {
+        (0x43, 0x47)
+    } else if &boot_sector[0x36..0x39] == b"FAT" {
+        (0x27, 0x2b)
+    } else {
+        return None;
+    };
+
+    let serial = u32::from_le_bytes(boot_sector[serial_offset..serial_offset + 4].try_into().unwrap());
+
+    Some(VolumeId {
+        uuid: Some(format!("{:04X}-{:04X}", serial >> 16, serial & 0xffff)),
+        label: parse_label(&boot_sector[label_offset..label_offset + 11]),
+    })
+}
+
+pub fn read_volume_id(disk_io: &DiskIo, media_id: u32) -> VolumeId {
+    let mut start = [0u8; 2048];
+    if disk_io.read_disk(media_id, 0, &mut start).is_err() {
+        return VolumeId::default();
+    }
+
+    ext4(&start[EXT4_SUPERBLOCK..]).or_else(|| fat(&start)).unwrap_or_default()
+}
@@ -0,0 +1,161 @@
+/*
+This file contains a minimal ELF parser that is used to load Multiboot2 kernels and plain ELF executables.
+Only the program headers are parsed and only PT_LOAD segments are loaded (at their physical address).
+https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.eheader.html
+*/
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+const PT_LOAD: u32 = 1;
+
+pub const EM_386: u16 = 3;
+pub const EM_X86_64: u16 = 0x3e;
+
+pub struct Segment {
+    pub offset: usize,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+pub struct Elf<'a> {
+    image: &'a [u8],
+    pub is_64bit: bool,
+    pub machine: u16,
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+}
+
+fn read_u16(image: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(image[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(image: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(image: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(image[offset..offset + 8].try_into().unwrap())
+}
+
+pub fn is_elf(image: &[u8]) -> bool {
+    image.starts_with(b"\x7fELF")
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(image: &'a [u8]) -> SimpleResult<Elf<'a>> {
+        if !is_elf(image) || image.len() < 0x34 {
+            return simple_error!("Not an ELF file");
+        }
+
+        let is_64bit = match image[4] {
+            1 => false,
+            2 => true,
+            class => return simple_error!("Unknown ELF class {class}"),
+        };
+
+        if image[5] != 1 {
+            return simple_error!("Only little endian ELF files are supported");
+        }
+
+        let machine = read_u16(image, 0x12);
+
+        let (entry, phoff, phentsize, phnum) = if is_64bit {
+            if image.len() < 0x40 {
+                return simple_error!("ELF header is truncated");
+            }
+            (read_u64(image, 0x18), read_u64(image, 0x20) as usize, read_u16(image, 0x36) as usize, read_u16(image, 0x38) as usize)
+        } else {
+            (read_u32(image, 0x18) as u64, read_u32(image, 0x1c) as usize, read_u16(image, 0x2a) as usize, read_u16(image, 0x2c) as usize)
+        };
+
+        let min_phentsize = if is_64bit { 0x38 } else { 0x20 };
+        let table_end = phnum.checked_mul(phentsize).and_then(|size| phoff.checked_add(size));
+        let program_headers = match table_end {
+            _ if phnum == 0 => &image[..0],
+            Some(table_end) if phentsize >= min_phentsize && table_end <= image.len() => &image[phoff..table_end],
+            _ => return simple_error!("ELF program headers are invalid or truncated"),
+        };
+
+        let mut segments = Vec::new();
+
+        for ph in program_headers.chunks_exact(phentsize.max(1)) {
+
+            if read_u32(ph, 0) != PT_LOAD {
+                continue;
+            }
+
+            let segment = if is_64bit {
+                Segment {
+                    offset: read_u64(ph, 0x08) as usize,
+                    paddr: read_u64(ph, 0x18),
+                    filesz: read_u64(ph, 0x20),
+                    memsz: read_u64(ph, 0x28),
+                }
+            } else {
+                Segment {
+                    offset: read_u32(ph, 0x04) as usize,
+                    paddr: read_u32(ph, 0x0c) as u64,
+                    filesz: read_u32(ph, 0x10) as u64,
+                    memsz: read_u32(ph, 0x14) as u64,
+                }
+            };
+
+            if segment.filesz > segment.memsz
+                || segment.paddr.checked_add(segment.memsz).is_none()
+                || segment.offset.saturating_add(segment.filesz as usize) > image.len() {
+                return simple_error!("ELF segment at {:#x} is invalid or truncated", segment.paddr);
+            }
+
+            if segment.memsz > 0 {
+                segments.push(segment);
+            }
+        }
+
+        if segments.is_empty() {
+            return simple_error!("ELF file has no loadable segments");
+        }
+
+        Ok(Elf { image, is_64bit, machine, entry, segments })
+    }
+
+    // Copies all segments to their physical addresses after making sure that they do not overlap and that the
+    // memory is not used by anything else. The memory beyond filesz (bss) is zeroed.
+    pub fn load(&self) -> SimpleResult<()> {
+        let mut ranges: Vec<(u64, u64)> = self.segments.iter().map(|segment| (segment.paddr, segment.paddr + segment.memsz)).collect();
+        ranges.sort();
+
+        for pair in ranges.windows(2) {
+            if pair[0].1 > pair[1].0 {
+                return simple_error!("ELF segments {:#x}-{:#x} and {:#x}-{:#x} overlap", pair[0].0, pair[0].1, pair[1].0, pair[1].1);
+            }
+        }
+
+        // segments may share a page so we allocate the page aligned ranges after merging them
+        let mut pages: Vec<(u64, u64)> = Vec::new();
+        for (start, end) in ranges {
+            let (start, end) = (start & !0xfff, end.div_ceil(4096) * 4096);
+
+            match pages.last_mut() {
+                Some(last) if last.1 >= start => last.1 = last.1.max(end),
+                _ => pages.push((start, end)),
+            }
+        }
+
+        for (start, end) in pages {
+            crate::mem::allocate_range(start, end)?;
+        }
+
+        for segment in &self.segments {
+            let data = &self.image[segment.offset..segment.offset + segment.filesz as usize];
+            unsafe {
+                core::ptr::copy(data.as_ptr(), segment.paddr as *mut u8, data.len());
+            }
+        }
+        Ok(())
+    }
+}
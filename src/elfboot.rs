@@ -0,0 +1,236 @@
+/*
+This file boots plain static ELF64 executables (e.g. firmware test payloads). The PT_LOAD segments are loaded at
+their physical addresses and e_entry is called in long mode with the identity map from mem::paging, the GDT from
+mem::gdt and a pointer to BootInfo in rdi. See documentation/elf_boot_protocol.md for the details.
+*/
+
+extern crate alloc;
+
+use core::arch::asm;
+use core::mem::size_of;
+
+use alloc::{string::String, vec::Vec};
+use uefi::boot::{self, MemoryType};
+use uefi::mem::memory_map::{MemoryMap, MemoryMapMut};
+use uefi::println;
+
+use crate::elf::{Elf, EM_X86_64};
+use crate::kernel::Kernel;
+use crate::simple_error::{simple_error, SimpleResult};
+
+pub const BOOT_INFO_MAGIC: u64 = u64::from_le_bytes(*b"bs2boot\0");
+pub const BOOT_INFO_VERSION: u32 = 1;
+
+const STACK_PAGES: usize = 16;
+
+// All addresses are physical (and identity mapped), strings are NUL-terminated.
+#[repr(C)]
+pub struct BootInfo {
+    pub magic: u64,                 // BOOT_INFO_MAGIC
+    pub version: u32,               // BOOT_INFO_VERSION
+    pub size: u32,                  // size of this struct
+    pub memory_map: u64,            // array of MemoryRegion sorted by address
+    pub memory_map_entries: u64,
+    pub framebuffer: Framebuffer,
+    pub rsdp: u64,                  // 0 if there are no ACPI tables
+    pub cmdline: u64,
+    pub modules: u64,               // array of Module
+    pub module_count: u64,
+}
+
+#[repr(C)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub size: u64,
+    pub typ: u32,                   // E820 type (1 = usable RAM, 2 = reserved, 3 = ACPI, 4 = ACPI NVS, ...)
+    pub _reserved: u32,
+}
+
+// addr is 0 if there is no framebuffer
+#[repr(C)]
+pub struct Framebuffer {
+    pub addr: u64,
+    pub width: u32,
+    pub height: u32,
+    pub pitch: u32,                 // bytes per line
+    pub bits_per_pixel: u16,
+    pub red_pos: u8,
+    pub red_size: u8,
+    pub green_pos: u8,
+    pub green_size: u8,
+    pub blue_pos: u8,
+    pub blue_size: u8,
+    pub _reserved: u32,
+}
+
+#[repr(C)]
+pub struct Module {
+    pub start: u64,
+    pub size: u64,
+    pub cmdline: u64,
+}
+
+pub struct ElfExecutable {
+    image: Vec<u8>,
+    modules: Vec<(Vec<u8>, String)>,
+}
+
+impl ElfExecutable {
+    pub fn new(image: Vec<u8>) -> SimpleResult<Self> {
+        crate::secure_boot::refuse_unsigned_format("ELF")?;
+
+        let elf = Elf::parse(&image)?;
+
+        if !elf.is_64bit || elf.machine != EM_X86_64 {
+            return simple_error!("Only x86_64 ELF64 executables are supported");
+        }
+
+        Ok(ElfExecutable { image, modules: Vec::new() })
+    }
+
+    // modules are only copied to their final location when the executable is started
+    pub fn add_module(&mut self, data: Vec<u8>, cmdline: &str) {
+        self.modules.push((data, cmdline.into()));
+    }
+
+    // only returns if something went wrong
+    pub fn start(&mut self, cmdline: &str) -> SimpleResult<()> {
+        let result = self.load_and_boot(cmdline);
+        crate::mem::free_tracked_allocations();
+        result
+    }
+
+    fn framebuffer() -> Framebuffer {
+        let mut framebuffer: Framebuffer = unsafe { core::mem::zeroed() };
+
+        let Some(mut gop) = crate::video::open_gop() else {
+            return framebuffer;
+        };
+
+        let mode_info = gop.current_mode_info();
+        let Some(layout) = crate::video::pixel_layout(&mode_info) else {
+            return framebuffer;
+        };
+
+        framebuffer.addr = gop.frame_buffer().as_mut_ptr() as u64;
+        (framebuffer.width, framebuffer.height) = (mode_info.resolution().0 as u32, mode_info.resolution().1 as u32);
+        framebuffer.pitch = (mode_info.stride() * layout.bits_per_pixel as usize / 8) as u32;
+        framebuffer.bits_per_pixel = layout.bits_per_pixel;
+        (framebuffer.red_pos, framebuffer.red_size) = layout.red;
+        (framebuffer.green_pos, framebuffer.green_size) = layout.green;
+        (framebuffer.blue_pos, framebuffer.blue_size) = layout.blue;
+        framebuffer
+    }
+
+    // measured like the kernel and initrd of a Linux boot
+    fn measure(&self, cmdline: &str) -> SimpleResult<()> {
+        crate::tpm::measure_file(&self.image, "ELF executable")?;
+        crate::tpm::measure_cmdline(cmdline)?;
+
+        for (data, module_cmdline) in &self.modules {
+            crate::tpm::measure_file(data, "ELF boot module")?;
+            crate::tpm::measure_cmdline(module_cmdline)?;
+        }
+        Ok(())
+    }
+
+    fn load_and_boot(&mut self, cmdline: &str) -> SimpleResult<()> {
+        const SLACK_ENTRIES: usize = 64;    // the memory map may still grow until exit_boot_services
+
+        self.measure(cmdline)?;
+
+        // the segments have fixed addresses so they are loaded before anything else is allocated
+        let elf = Elf::parse(&self.image)?;
+        elf.load()?;
+        println!("Loaded {} segments, entry point is at {:#x}", elf.segments.len(), elf.entry);
+
+        // layout of the boot info pages: BootInfo | modules | memory map | strings
+        let mmap_capacity = boot::memory_map(MemoryType::LOADER_DATA)?.len() + SLACK_ENTRIES;
+        let modules_offset = size_of::<BootInfo>();
+        let mmap_offset = modules_offset + self.modules.len() * size_of::<Module>();
+        let strings_offset = mmap_offset + mmap_capacity * size_of::<MemoryRegion>();
+        let strings_size: usize = cmdline.len() + 1 + self.modules.iter().map(|(_, cmdline)| cmdline.len() + 1).sum::<usize>();
+
+        let base = crate::mem::allocate_pages((strings_offset + strings_size).div_ceil(4096));
+        if base == 0 {
+            return simple_error!("Could not allocate memory for the boot info");
+        }
+
+        // the pages are zeroed so the strings are NUL-terminated already
+        let mut next_string = base + strings_offset;
+        let mut push_string = |string: &str| {
+            let addr = next_string;
+            unsafe { core::ptr::copy(string.as_ptr(), addr as *mut u8, string.len()); }
+            next_string += string.len() + 1;
+            addr as u64
+        };
+
+        let boot_info = unsafe { &mut *(base as *mut BootInfo) };
+        boot_info.magic = BOOT_INFO_MAGIC;
+        boot_info.version = BOOT_INFO_VERSION;
+        boot_info.size = size_of::<BootInfo>() as u32;
+        boot_info.memory_map = (base + mmap_offset) as u64;
+        boot_info.framebuffer = ElfExecutable::framebuffer();
+        boot_info.rsdp = crate::acpi::rsdp_addr().unwrap_or(0);
+        boot_info.cmdline = push_string(cmdline);
+        boot_info.modules = (base + modules_offset) as u64;
+        boot_info.module_count = self.modules.len() as u64;
+
+        for (idx, (data, module_cmdline)) in self.modules.iter().enumerate() {
+            let start = crate::mem::copy_buf_below(data, u32::MAX as usize)?;
+
+            let module = Module { start: start as u64, size: data.len() as u64, cmdline: push_string(module_cmdline) };
+            unsafe { (boot_info.modules as *mut Module).add(idx).write(module); }
+        }
+
+        let gdtr = crate::mem::gdt::create_simple_gdtr(true);
+        println!("Building page tables...");
+        let pml4_ptr = unsafe { crate::mem::paging::prepare_identity_mapped_pml4() } as usize;
+
+        let stack = crate::mem::allocate_pages(STACK_PAGES);
+        if stack == 0 {
+            return simple_error!("Could not allocate a stack");
+        }
+        let stack_top = stack + STACK_PAGES * 4096;
+
+        println!("Exiting boot services, bye...");
+
+        unsafe {
+            let mut mmap = boot::exit_boot_services(MemoryType::LOADER_DATA);
+            mmap.sort();
+
+            // this must not allocate anymore, entries that do not fit are dropped
+            let regions = boot_info.memory_map as *mut MemoryRegion;
+            for (idx, entry) in mmap.entries().take(mmap_capacity).enumerate() {
+                regions.add(idx).write(MemoryRegion {
+                    start: entry.phys_start,
+                    size: entry.page_count * 4096,
+                    typ: Kernel::e820_type(entry.ty),
+                    _reserved: 0,
+                });
+                boot_info.memory_map_entries = idx as u64 + 1;
+            }
+
+            crate::mem::gdt::set_gdtr(&gdtr);
+
+            ElfExecutable::run(pml4_ptr, stack_top, elf.entry as usize, base);
+        }
+    }
+
+    // like a call to entry(boot_info) from a function with a 16 byte aligned stack (System V ABI)
+    unsafe fn run(page_table_addr: usize, stack_top: usize, entry_point: usize, boot_info: usize) -> ! {
+        asm!(
+            "cli",
+            "mov cr3, {}",
+            "mov rsp, {}",
+            "xor rbp, rbp",
+            "push 0",
+            "jmp {}",
+            in(reg) page_table_addr,
+            in(reg) stack_top,
+            in(reg) entry_point,
+            in("rdi") boot_info,
+            options(noreturn),
+        );
+    }
+}
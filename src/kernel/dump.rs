@@ -0,0 +1,65 @@
+// Pretty-prints the zero page so that `runkernel --dry-run` can show what the kernel would get.
+
+use uefi::println;
+
+use super::params::*;
+
+fn e820_type_as_str(typ: u32) -> &'static str {
+    match typ {
+        E820_TYPE_RAM => "RAM",
+        E820_TYPE_RESERVED => "Reserved",
+        E820_TYPE_ACPI => "ACPI",
+        E820_TYPE_NVS => "ACPI NVS",
+        E820_TYPE_UNUSABLE => "Unusable",
+        E820_TYPE_PMEM => "Persistent memory",
+        _ => "Unknown",
+    }
+}
+
+// reads the NUL-terminated command line the boot params point to
+fn cmdline(boot_params: &BootParams) -> &str {
+    let ptr = boot_params.kernel_header.cmd_line_ptr as usize as *const u8;
+    if ptr.is_null() {
+        return "";
+    }
+
+    let len = (0..).take_while(|&i| unsafe { *ptr.add(i) } != 0).count();
+    let bytes = unsafe { core::slice::from_raw_parts(ptr, len) };
+    core::str::from_utf8(bytes).unwrap_or("(invalid UTF-8)")
+}
+
+pub fn print_boot_params(boot_params: &BootParams) {
+    let header = &boot_params.kernel_header;
+    let screen_info = &boot_params.screen_info;
+
+    println!("Kernel header:");
+    println!("  protocol version: {}.{:02}", { header.version } >> 8, { header.version } & 0xFF);
+    println!("  code32_start: {:#x}", { header.code32_start });
+    println!("  cmd_line_ptr: {:#x} \"{}\"", { header.cmd_line_ptr }, cmdline(boot_params));
+    println!("  ramdisk: {:#x} size: {}", { header.ramdisk_image }, { header.ramdisk_size });
+    println!("  setup_data: {:#x}", { header.setup_data });
+    println!("  type_of_loader: {:#x} vid_mode: {:#x}", { header.type_of_loader }, { header.vid_mode });
+    println!("  xloadflags: {:#06b} handover_offset: {:#x}", { header.xloadflags }, { header.handover_offset });
+
+    println!("Zero page:");
+    println!("  acpi_rsdp_addr: {:#x}", { boot_params.acpi_rsdp_addr });
+    println!("  EDID: {}", if boot_params.edid_info.iter().any(|&byte| byte != 0) { "present" } else { "none" });
+
+    if screen_info.orig_video_is_vga == 0 {
+        println!("  screen_info: (not set)");
+    } else {
+        let lfb_base = { screen_info.lfb_base } as u64 | (({ screen_info.ext_lfb_base } as u64) << 32);
+        println!("  screen_info: {}x{} {} bpp, stride {} bytes, framebuffer at {:#x} size {}",
+            { screen_info.lfb_width }, { screen_info.lfb_height }, { screen_info.lfb_depth },
+            { screen_info.lfb_linelength }, lfb_base, { screen_info.lfb_size });
+        println!("    red {}@{} green {}@{} blue {}@{} reserved {}@{}",
+            screen_info.red_size, screen_info.red_pos, screen_info.green_size, screen_info.green_pos,
+            screen_info.blue_size, screen_info.blue_pos, screen_info.rsvd_size, screen_info.rsvd_pos);
+    }
+
+    println!("  e820 entries: {}", boot_params.e820_entries);
+    for entry in &boot_params.e820_table[..boot_params.e820_entries as usize] {
+        let (addr, size, typ) = (entry.addr, entry.size, entry.typ);
+        println!("    [{:#018x}-{:#018x}] {}", addr, addr + size - 1, e820_type_as_str(typ));
+    }
+}
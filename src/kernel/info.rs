@@ -0,0 +1,131 @@
+// This file prints what we know about a kernel image without loading it (`kernelinfo` command).
+// https://www.kernel.org/doc/html/v6.6/arch/x86/boot.html#the-real-mode-kernel-header
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use uefi::println;
+
+use crate::simple_error::SimpleResult;
+
+use super::params::*;
+use super::Kernel;
+
+const XLOADFLAGS: [&str; 8] = [
+    "XLF_KERNEL_64",
+    "XLF_CAN_BE_LOADED_ABOVE_4G",
+    "XLF_EFI_HANDOVER_32",
+    "XLF_EFI_HANDOVER_64",
+    "XLF_EFI_KEXEC",
+    "XLF_5LEVEL",
+    "XLF_5LEVEL_ENABLED",
+    "XLF_MEM_ENCRYPTION",
+];
+
+// the same magic numbers the kernel's decompressor looks for
+pub fn payload_format(payload: &[u8]) -> &'static str {
+    match payload {
+        [0x1f, 0x8b, ..] => "gzip",
+        [0x1f, 0x9e, ..] => "old gzip",     // the magic of gzip 0.5, we cannot decompress it
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => "xz",
+        [0x5d, 0x00, 0x00, ..] => "lzma",
+        [b'B', b'Z', b'h', ..] => "bzip2",
+        [0x89, b'L', b'Z', b'O', ..] => "lzo",
+        [0x02, 0x21, 0x4c, 0x18, ..] => "lz4",
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => "zstd",
+        [0x7f, b'E', b'L', b'F', ..] => "uncompressed ELF",
+        _ => "unknown",
+    }
+}
+
+// the offset of the protected-mode code in the image (setup_sects == 0 means 4 for historical reasons)
+pub fn protected_mode_offset(kernel_header: &KernelHeader) -> usize {
+    let setup_sects = match kernel_header.setup_sects {
+        0 => 4,
+        setup_sects => setup_sects as usize,
+    };
+    (setup_sects + 1) * 512
+}
+
+// kernels built with CONFIG_EFI_STUB are also PE images
+pub fn has_pe_stub(image: &[u8]) -> bool {
+    if image.len() < 0x40 || &image[..2] != b"MZ" {
+        return false;
+    }
+
+    let pe_offset = u32::from_le_bytes(image[0x3c..0x40].try_into().unwrap()) as usize;
+    image.get(pe_offset..pe_offset + 4) == Some(b"PE\0\0")
+}
+
+// The version string is at kernel_version + 0x200 and NUL-terminated
+fn kernel_version_string<'a>(image: &'a [u8], kernel_header: &KernelHeader) -> Option<&'a str> {
+    if kernel_header.version < 0x0200 || kernel_header.kernel_version == 0 {
+        return None;
+    }
+
+    let start = kernel_header.kernel_version as usize + 0x200;
+    let bytes = image.get(start..)?;
+    let len = bytes.iter().position(|&byte| byte == 0)?;
+
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+pub fn print_kernel_info(image: &[u8]) -> SimpleResult<()> {
+    let kernel_header = &KernelHeader::from_image(image)?;
+    let version = kernel_header.version;
+
+    if kernel_header.header != 0x53726448 {    // header should be "HdrS"
+        println!("No \"HdrS\" signature, this is not a bzImage (or the protocol is older than 2.00)");
+    } else {
+        println!("Boot protocol version: {}.{:02}", version >> 8, version & 0xFF);
+    }
+
+    if let Some(version_string) = kernel_version_string(image, kernel_header) {
+        println!("Kernel version: {version_string}");
+    }
+
+    println!("Relocatable: {}", if version >= 0x0205 && kernel_header.relocatable_kernel != 0 { "yes" } else { "no" });
+    println!("code32_start: {:#x}", { kernel_header.code32_start });
+
+    if version >= 0x020c {
+        let xloadflags = kernel_header.xloadflags;
+        let names: Vec<&str> = XLOADFLAGS
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| xloadflags & (1 << bit) != 0)
+            .map(|(_, name)| *name)
+            .collect();
+
+        println!("xloadflags: {:#06x} {}", xloadflags, names.join(" "));
+    }
+
+    if version >= 0x020b {
+        println!("handover_offset: {:#x}", { kernel_header.handover_offset });
+    }
+
+    if version >= 0x020a {
+        println!("init_size: {}", crate::disk::human_readable_size({ kernel_header.init_size } as u64).trim());
+    }
+
+    if version >= 0x0208 {
+        let payload_start = protected_mode_offset(kernel_header) + kernel_header.payload_offset as usize;
+        let payload_end = payload_start + kernel_header.payload_length as usize;
+
+        match image.get(payload_start..payload_end) {
+            Some(payload) => println!(
+                "Payload: {} ({})",
+                payload_format(payload),
+                crate::disk::human_readable_size(payload.len() as u64).trim()
+            ),
+            None => println!("Payload: outside of the image (truncated file?)"),
+        }
+    }
+
+    println!("PE/EFI stub: {}", if has_pe_stub(image) { "yes" } else { "no" });
+
+    match Kernel::check_support(kernel_header) {
+        Ok(()) => println!("This kernel can be started with runkernel"),
+        Err(err) => println!("This kernel cannot be started: {err}"),
+    }
+    Ok(())
+}
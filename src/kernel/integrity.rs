@@ -0,0 +1,164 @@
+// This file checks a kernel image and its initrd while boot services are still running, so a truncated or
+// corrupt file is reported instead of hanging the machine after exit_boot_services.
+
+extern crate alloc;
+
+use uefi::println;
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+use super::info::{has_pe_stub, payload_format, protected_mode_offset};
+use super::params::KernelHeader;
+
+fn read_u16(image: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(image[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(image: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn check_kernel(image: &[u8], kernel_header: &KernelHeader) -> SimpleResult<()> {
+    // syssize is the size of the protected-mode code in 16 byte units since 2.04
+    let protected_mode_end = protected_mode_offset(kernel_header) + kernel_header.syssize as usize * 16;
+    if kernel_header.version >= 0x0204 && protected_mode_end > image.len() {
+        return simple_error!("The kernel image is truncated ({} bytes, expected at least {protected_mode_end})", image.len());
+    }
+
+    if has_pe_stub(image) {
+        check_pe_headers(image)?;
+    }
+
+    check_payload(image, kernel_header)
+}
+
+// the sections of the EFI stub must be inside the file and the entry point inside the image
+// https://learn.microsoft.com/en-us/windows/win32/debug/pe-format
+fn check_pe_headers(image: &[u8]) -> SimpleResult<()> {
+    const IMAGE_FILE_MACHINE_I386: u16 = 0x14c;
+    const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+
+    let pe_offset = read_u32(image, 0x3c) as usize;
+    let Some(coff_header) = image.get(pe_offset + 4..pe_offset + 24) else {
+        return simple_error!("The PE header of the kernel is truncated");
+    };
+
+    let machine = read_u16(coff_header, 0);
+    let section_count = read_u16(coff_header, 2) as usize;
+    let optional_header_size = read_u16(coff_header, 16) as usize;
+
+    if machine != IMAGE_FILE_MACHINE_AMD64 && machine != IMAGE_FILE_MACHINE_I386 {
+        return simple_error!("The PE header of the kernel has an unexpected machine type {machine:#x}");
+    }
+
+    let optional_header_offset = pe_offset + 24;
+    let Some(optional_header) = image.get(optional_header_offset..optional_header_offset + optional_header_size) else {
+        return simple_error!("The PE optional header of the kernel is truncated");
+    };
+
+    // the fields up to SizeOfImage are at the same offsets for PE32 and PE32+
+    if optional_header.len() < 60 || !matches!(read_u16(optional_header, 0), 0x10b | 0x20b) {
+        return simple_error!("The PE optional header of the kernel is invalid");
+    }
+
+    let entry_point = read_u32(optional_header, 16);
+    let image_size = read_u32(optional_header, 56);
+    if entry_point >= image_size {
+        return simple_error!("The PE entry point {entry_point:#x} is outside of the image ({image_size:#x} bytes)");
+    }
+
+    let section_table_offset = optional_header_offset + optional_header_size;
+    let Some(section_table) = image.get(section_table_offset..section_table_offset + section_count * 40) else {
+        return simple_error!("The PE section table of the kernel is truncated");
+    };
+
+    for section in section_table.chunks(40) {
+        let name = crate::acpi::ascii_to_string(&section[..8]);
+        let raw_size = read_u32(section, 16) as usize;
+        let raw_offset = read_u32(section, 20) as usize;
+
+        if raw_offset + raw_size > image.len() {
+            return simple_error!("The PE section {} ends at {:#x} but the kernel image only has {:#x} bytes",
+                name.trim_end_matches('\0'), raw_offset + raw_size, image.len());
+        }
+    }
+    Ok(())
+}
+
+// Decompresses the payload to check that it is intact. Apart from gzip all formats have the decompressed size
+// appended (size_append in the kernel's Makefile.lib), the decompressed payload is vmlinux (an ELF file).
+fn check_payload(image: &[u8], kernel_header: &KernelHeader) -> SimpleResult<()> {
+    if kernel_header.version < 0x0208 {
+        println!("The kernel is too old to have payload_offset, skipping the payload check");
+        return Ok(());
+    }
+
+    let payload_start = protected_mode_offset(kernel_header) + kernel_header.payload_offset as usize;
+    let payload_end = payload_start + kernel_header.payload_length as usize;
+
+    let Some(payload) = image.get(payload_start..payload_end) else {
+        return simple_error!("The kernel payload ({payload_start:#x}-{payload_end:#x}) is outside of the image, the file is probably truncated");
+    };
+
+    let format = payload_format(payload);
+    let decompress = match format {
+        "gzip" => crate::decompress::gunzip,
+        "xz" => crate::decompress::unxz,
+        "lzma" => crate::decompress::unlzma,
+        "lz4" => crate::decompress::unlz4_legacy,
+        "zstd" => crate::decompress::unzstd,
+        "uncompressed ELF" => return Ok(()),
+        format => {
+            println!("Cannot check {format} compressed kernel payloads, skipping the payload check");
+            return Ok(());
+        },
+    };
+
+    println!("Checking the {format} compressed kernel payload...");
+
+    let (compressed, expected_size) = match format {
+        "gzip" => (payload, None),
+        _ if payload.len() >= 4 => {
+            let (compressed, size) = payload.split_at(payload.len() - 4);
+            (compressed, Some(read_u32(size, 0) as usize))
+        },
+        _ => return simple_error!("The kernel payload is truncated"),
+    };
+
+    let decompressed = match decompress(compressed) {
+        Ok(decompressed) => decompressed,
+        Err(err) => return simple_error!("The kernel payload is corrupt: {err}"),
+    };
+
+    if let Some(expected_size) = expected_size {
+        if decompressed.len() != expected_size {
+            return simple_error!("The kernel payload decompressed to {} bytes instead of {expected_size}", decompressed.len());
+        }
+    }
+
+    if !crate::elf::is_elf(&decompressed) {
+        return simple_error!("The decompressed kernel payload is not an ELF file");
+    }
+    Ok(())
+}
+
+// Returns the format of the initrd. Only the first archive is looked at; initrds often consist of an uncompressed
+// cpio with early microcode followed by a compressed one. Unknown formats are only a warning because the kernel also
+// accepts file system images (ext2, squashfs, cramfs) as initrd.
+pub fn check_initrd(initrd: &[u8]) -> SimpleResult<&'static str> {
+    if initrd.starts_with(b"070701") || initrd.starts_with(b"070702") {
+        // the newc header is 110 bytes followed by the file name
+        if initrd.len() < 110 {
+            return simple_error!("The initrd is a truncated cpio archive");
+        }
+        return Ok("cpio (newc)");
+    }
+
+    match payload_format(initrd) {
+        "unknown" | "uncompressed ELF" => {
+            println!("Warning: the initrd is neither a cpio archive nor a compressed one, booting it anyway");
+            Ok("unknown")
+        },
+        format => Ok(format),
+    }
+}
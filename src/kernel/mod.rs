@@ -2,24 +2,31 @@
 
 extern crate alloc;
 
+mod dump;
+mod info;
+mod integrity;
 mod params;
+mod random_seed;
+pub mod setup_data;
 
 use core::arch::asm;
 
 use alloc::vec::Vec;
 use uefi::boot::{self, MemoryType};
-use uefi::mem::memory_map::{MemoryMap, MemoryMapOwned};
+use uefi::mem::memory_map::{MemoryMap, MemoryMapMut, MemoryMapOwned};
 use uefi::println;
-use uefi::proto::console::gop::GraphicsOutput;
 
-use crate::disk::open_protocol_unsafe;
 use crate::mem::allocate_low_pages;
-use crate::{mem::copy_buf_to_aligned_address, simple_error::{simple_error, SimpleResult}};
+use crate::mem::protected_mode::EntryRegisters;
+use crate::{mem::copy_buf_below, simple_error::{simple_error, SimpleResult}};
 
+pub use self::info::print_kernel_info;
 use self::params::*;
+use self::setup_data::*;
 
 pub struct Kernel {
     image: Vec<u8>,
+    setup_data: SetupDataChain,
 }
 
 impl Kernel {
@@ -30,11 +37,17 @@ impl Kernel {
 
         let kernel = Kernel {
             image,
+            setup_data: SetupDataChain::new(),
         };
 
         Ok(kernel)
     }
 
+    // the node is linked into the boot params when the kernel is started
+    pub fn add_setup_data(&mut self, entry: SetupDataEntry) -> SimpleResult<()> {
+        self.setup_data.add(entry)
+    }
+
     fn check_support(kernel_header: &KernelHeader) -> SimpleResult<()> {
         if kernel_header.boot_flag != 0xAA55 {
             return simple_error!("Kernel image does not have the correct magic number");
@@ -44,20 +57,19 @@ impl Kernel {
             return simple_error!("Kernel does not have a valid header");
         }
 
-        if kernel_header.version < 0x020c {
-            return simple_error!("Kernel does not support boot protocol version 2.12");
+        // cmd_line_ptr was added in 2.02, older kernels need the command line at a fixed address in the setup area
+        if kernel_header.version < 0x0202 {
+            return simple_error!("Kernel uses boot protocol older than 2.02 which is not supported");
         }
 
-        if kernel_header.relocatable_kernel == 0 {
-            // kernels with protocol version >= 2.12 should be relocatable anyway
-            return simple_error!("Kernel is not relocatable! This code only works for relocatable kernels :(");
+        if kernel_header.load_flags & LOADED_HIGH == 0 {
+            return simple_error!("Kernel is a zImage which has to be loaded below 1M, only bzImages are supported");
         }
         Ok(())
     }
 
     fn extract_protected_mode_kernel_to_aligned_address(&mut self, boot_params: &mut BootParams) -> usize {
-        let setup_code_size = boot_params.kernel_header.setup_sects as usize * 512;
-        let protected_mode_kernel_start = (setup_code_size + 512).try_into().unwrap();
+        let protected_mode_kernel_start = info::protected_mode_offset(&boot_params.kernel_header);
 
         let addr = crate::mem::copy_buf_to_aligned_address(&self.image[protected_mode_kernel_start..]);
 
@@ -70,17 +82,57 @@ impl Kernel {
         addr
     }
 
+    // Non-relocatable kernels have to be loaded at code32_start (1M). The kernel decompresses itself in place so
+    // we need room for init_size bytes; older protocols do not have it and like GRUB we reserve three times the size.
+    fn extract_protected_mode_kernel_to_fixed_address(&mut self, boot_params: &mut BootParams) -> SimpleResult<usize> {
+        let kernel_header = &boot_params.kernel_header;
+        let protected_mode_kernel = &self.image[info::protected_mode_offset(kernel_header)..];
+
+        let addr = kernel_header.code32_start as usize;
+        let size = if kernel_header.version >= 0x020a {
+            (kernel_header.init_size as usize).max(protected_mode_kernel.len())
+        } else {
+            protected_mode_kernel.len() * 3
+        };
+
+        crate::mem::allocate_pages_at(addr, size.div_ceil(4096))?;
+        unsafe {
+            core::ptr::copy(protected_mode_kernel.as_ptr(), addr as *mut u8, protected_mode_kernel.len());
+        }
+
+        println!("protected-mode kernel code copied to fixed address {:x}", addr);
+
+        Ok(addr)
+    }
+
+    // the kernel expects a NUL-terminated string (the pages are zeroed so we only need to copy the bytes)
     fn set_cmdline(boot_params: &mut BootParams, cmdline: &str) -> SimpleResult<()> {
-        let addr = allocate_low_pages(1)?;
-        unsafe { core::ptr::write(addr as *mut &[u8], cmdline.as_bytes()); }
+        // before 2.06 the maximum length was fixed
+        let max_len = match boot_params.kernel_header.version {
+            0x0206.. => boot_params.kernel_header.cmdline_size as usize,
+            _ => 255,
+        };
+
+        if cmdline.len() > max_len {
+            return simple_error!("The kernel command line is {} bytes long but the kernel only supports {max_len}", cmdline.len());
+        }
+
+        let addr = allocate_low_pages((cmdline.len() + 1).div_ceil(4096))?;
+        unsafe { core::ptr::copy(cmdline.as_ptr(), addr as *mut u8, cmdline.len()); }
         boot_params.kernel_header.cmd_line_ptr = addr as u32;
         Ok(())
     }
 
     // TODO: check if ramdisk works correctly
-    fn set_ramdisk(boot_params: &mut BootParams, ramdisk: Option<Vec<u8>>) {
+    fn set_ramdisk(boot_params: &mut BootParams, ramdisk: Option<Vec<u8>>) -> SimpleResult<()> {
         if let Some(ramdisk) = ramdisk {
-            let ramdisk_addr = copy_buf_to_aligned_address(ramdisk.as_slice());
+            // initrd_addr_max was added in 2.03, older kernels can address 0x37FFFFFF
+            let initrd_addr_max = match boot_params.kernel_header.version {
+                0x0203.. => boot_params.kernel_header.initrd_addr_max as usize,
+                _ => 0x37FFFFFF,
+            };
+
+            let ramdisk_addr = copy_buf_below(ramdisk.as_slice(), initrd_addr_max)?;
 
             boot_params.kernel_header.ramdisk_image = ramdisk_addr as u32;
             boot_params.kernel_header.ramdisk_size = ramdisk.len() as u32;
@@ -88,55 +140,139 @@ impl Kernel {
             boot_params.kernel_header.ramdisk_image = 0;
             boot_params.kernel_header.ramdisk_size = 0;
         }
+        Ok(())
     }
 
-    fn set_memory_map(boot_params: &mut BootParams, mmap: &MemoryMapOwned) {
-        const MAX_E820_ENTRIES: usize = 128;
+    // Multiboot2 and the ELF boot info use the same types
+    pub fn e820_type(ty: MemoryType) -> u32 {
+        match ty {
+            MemoryType::CONVENTIONAL => E820_TYPE_RAM,
+            MemoryType::BOOT_SERVICES_CODE => E820_TYPE_RAM,
+            MemoryType::BOOT_SERVICES_DATA => E820_TYPE_RAM,
+            MemoryType::LOADER_CODE => E820_TYPE_RAM,
+            MemoryType::LOADER_DATA => E820_TYPE_RAM,
+            MemoryType::ACPI_RECLAIM => E820_TYPE_ACPI,
+            MemoryType::ACPI_NON_VOLATILE => E820_TYPE_NVS,
+            MemoryType::UNUSABLE => E820_TYPE_UNUSABLE,
+            MemoryType::PERSISTENT_MEMORY => E820_TYPE_PMEM,
+            _ => E820_TYPE_RESERVED,
+        }
+    }
+
+    // This runs after exit_boot_services so it must not allocate. Entries that do not fit into the zero page
+    // are written to the SETUP_E820_EXT node that was reserved beforehand.
+    fn set_memory_map(boot_params: &mut BootParams, mmap: &mut MemoryMapOwned, e820_ext: &SetupDataNode) {
+        mmap.sort();
+
+        let mut count = 0;
+        let mut current: Option<E820Entry> = None;
 
-        let mut i = 0;
         for entry in mmap.entries() {
-            let typ = match entry.ty {
-                MemoryType::CONVENTIONAL => E820_TYPE_RAM,
-                MemoryType::BOOT_SERVICES_CODE => E820_TYPE_RAM,
-                MemoryType::BOOT_SERVICES_DATA => E820_TYPE_RAM,
-                MemoryType::LOADER_CODE => E820_TYPE_RAM,
-                MemoryType::LOADER_DATA => E820_TYPE_RAM,
-                _ => E820_TYPE_RESERVED,
-            };
+            let typ = Kernel::e820_type(entry.ty);
+            let addr = entry.phys_start;
+            let size = entry.page_count * 4096;
+
+            // coalesce contiguous ranges of the same type
+            if let Some(cur) = current {
+                if cur.typ == typ && cur.addr + cur.size == addr {
+                    current = Some(E820Entry { addr: cur.addr, size: cur.size + size, typ });
+                    continue;
+                }
+
+                if Kernel::push_e820_entry(boot_params, e820_ext, count, cur) {
+                    count += 1;
+                }
+            }
 
-            boot_params.e820_table[i] = E820Entry {
-                addr: entry.phys_start,
-                size: entry.page_count * 4096,
-                typ,
-            };
+            current = Some(E820Entry { addr, size, typ });
+        }
 
-            i += 1;
-            if i >= MAX_E820_ENTRIES {
-                break;
+        if let Some(cur) = current {
+            if Kernel::push_e820_entry(boot_params, e820_ext, count, cur) {
+                count += 1;
             }
         }
 
-        boot_params.e820_entries = i as u8;
+        boot_params.e820_entries = count.min(E820_MAX_ENTRIES_ZEROPAGE) as u8;
+
+        let ext_count = count.saturating_sub(E820_MAX_ENTRIES_ZEROPAGE);
+        e820_ext.set_len(ext_count * core::mem::size_of::<E820Entry>());
+    }
+
+    // returns false if there is no space left for the entry
+    fn push_e820_entry(boot_params: &mut BootParams, e820_ext: &SetupDataNode, idx: usize, entry: E820Entry) -> bool {
+        if idx < E820_MAX_ENTRIES_ZEROPAGE {
+            boot_params.e820_table[idx] = entry;
+            return true;
+        }
+
+        let ext_idx = idx - E820_MAX_ENTRIES_ZEROPAGE;
+        if (ext_idx + 1) * core::mem::size_of::<E820Entry>() > e820_ext.capacity() {
+            return false;
+        }
+
+        unsafe { (e820_ext.data() as *mut E820Entry).add(ext_idx).write(entry); }
+        true
+    }
+
+    // Memory for the SETUP_E820_EXT node has to be allocated before exiting boot services but the final memory map
+    // is only known afterwards, so we reserve room for some more entries than the memory map currently has.
+    fn reserve_e820_ext(&mut self) -> SimpleResult<SetupDataNode> {
+        const SLACK_ENTRIES: usize = 64;    // the memory map may still grow until exit_boot_services
+
+        let mmap_len = boot::memory_map(MemoryType::LOADER_DATA)?.len();
+        let capacity = (mmap_len + SLACK_ENTRIES).saturating_sub(E820_MAX_ENTRIES_ZEROPAGE);
+
+        self.setup_data.reserve(SETUP_E820_EXT, capacity * core::mem::size_of::<E820Entry>())
+    }
+
+    // Entries that do not fit into the reserved node would be dropped after exit_boot_services where we cannot
+    // report it anymore, so we refuse to boot if the current memory map does not fit with some room to spare.
+    // Every descriptor becomes at most one E820 entry.
+    fn check_e820_capacity(e820_ext: &SetupDataNode) -> SimpleResult<()> {
+        const EXIT_SLACK: usize = 8;        // exit_boot_services allocates the buffer for the final memory map
+
+        let mmap_len = boot::memory_map(MemoryType::LOADER_DATA)?.len();
+        let capacity = E820_MAX_ENTRIES_ZEROPAGE + e820_ext.capacity() / core::mem::size_of::<E820Entry>();
+
+        if mmap_len + EXIT_SLACK > capacity {
+            return simple_error!("The memory map has {mmap_len} entries but there is only room for {capacity} E820 entries");
+        }
+        Ok(())
     }
 
     // this is partially guessed and partially taken from grub2 source code because there was not much documentation available
     fn set_video_params(boot_params: &mut BootParams) {
         const VIDEO_TYPE_EFI: u8 = 0x70;    // linux kernel screen_info.h
 
-        let screen_info = &mut boot_params.screen_info;
+        let Some(mut gop) = crate::video::open_gop() else {
+            println!("No GOP found, skipping video setup");
+            return;
+        };
 
-        let gop_handle = boot::get_handle_for_protocol::<GraphicsOutput>().unwrap();
-        let mut gop = open_protocol_unsafe::<GraphicsOutput>(gop_handle).unwrap();
+        let mode_info = gop.current_mode_info();
+        println!("GOP pixel format: {:?}", mode_info.pixel_format());
 
-        uefi::println!("GOP pixel format: {:?}", gop.current_mode_info().pixel_format());
+        let Some(layout) = crate::video::pixel_layout(&mode_info) else {
+            println!("The current graphics mode has no framebuffer, skipping video setup");
+            return;
+        };
+
+        // early framebuffer drivers use this to find out the native timings of the panel
+        if let Some(edid) = crate::video::read_edid() {
+            let len = edid.len().min(crate::video::EDID_BLOCK_SIZE);
+            boot_params.edid_info[..len].copy_from_slice(&edid[..len]);
+        }
+
+        let screen_info = &mut boot_params.screen_info;
 
         // pretty sure about these
         screen_info.orig_video_page = 0;
         screen_info.orig_video_points = 16;
-        screen_info.lfb_width = gop.current_mode_info().resolution().0 as u16;
-        screen_info.lfb_height = gop.current_mode_info().resolution().1 as u16;
-        screen_info.lfb_depth = 32;
-        screen_info.lfb_linelength = gop.current_mode_info().stride() as u16 * (screen_info.lfb_depth >> 3);
+        screen_info.lfb_width = mode_info.resolution().0 as u16;
+        screen_info.lfb_height = mode_info.resolution().1 as u16;
+        screen_info.lfb_depth = layout.bits_per_pixel;
+        screen_info.lfb_linelength = (mode_info.stride() * layout.bits_per_pixel as usize / 8) as u16;
         screen_info.lfb_base = gop.frame_buffer().as_mut_ptr() as u32;
         screen_info.ext_lfb_base = (gop.frame_buffer().as_mut_ptr() as u64 >> 32) as u32;
         screen_info.capabilities |= 0b10;     // mark framebuffer address as 64 bit
@@ -144,16 +280,10 @@ impl Kernel {
         
         screen_info.orig_video_ega_bx = 0;
 
-        // for BGR8 (from grub2 source code)
-        // TODO: support pixel formats other than BGR8
-        screen_info.red_size = 8;
-        screen_info.red_pos = 16;
-        screen_info.green_size = 8;
-        screen_info.green_pos = 8;
-        screen_info.blue_size = 8;
-        screen_info.blue_pos = 0;
-        screen_info.rsvd_size = 8;
-        screen_info.rsvd_pos = 24;
+        (screen_info.red_pos, screen_info.red_size) = layout.red;
+        (screen_info.green_pos, screen_info.green_size) = layout.green;
+        (screen_info.blue_pos, screen_info.blue_size) = layout.blue;
+        (screen_info.rsvd_pos, screen_info.rsvd_size) = layout.reserved;
 
         // not so sure about these
         screen_info.orig_x = 0;
@@ -168,34 +298,84 @@ impl Kernel {
 
     }
 
-    fn normal_handover(&mut self, mut boot_params: BootParams) -> SimpleResult<()> {
+    fn normal_handover(&mut self, mut boot_params: BootParams, dry_run: bool) -> SimpleResult<()> {
         println!("Starting using normal handover");
 
+        // kernels before 2.12 (xloadflags is zeroed for them) only have the 32 bit entry point
+        let long_mode = boot_params.kernel_header.xloadflags & XLF_KERNEL_64 != 0;
+
         let _low_pages_for_kernel = crate::mem::allocate_low_pages(10)?; // TODO: check if necessary
 
-        let protected_mode_kernel_addr = self.extract_protected_mode_kernel_to_aligned_address(&mut boot_params);
-        let entry_point = protected_mode_kernel_addr + 0x200; // 64bit entry point is at +0x200 of protected-mode code
+        let protected_mode_kernel_addr = if boot_params.kernel_header.relocatable_kernel != 0 {
+            self.extract_protected_mode_kernel_to_aligned_address(&mut boot_params)
+        } else {
+            self.extract_protected_mode_kernel_to_fixed_address(&mut boot_params)?
+        };
+
+        // 64bit entry point is at +0x200 of protected-mode code, the 32bit one at the start
+        let entry_point = if long_mode { protected_mode_kernel_addr + 0x200 } else { protected_mode_kernel_addr };
         println!("Entry point is at {:x}", entry_point);
 
         Kernel::set_video_params(&mut boot_params);
+        self.setup_data.add_pci_roms()?;
 
-        let gdtr = crate::mem::gdt::create_simple_gdtr();
-        println!("Building page tables...");
-        let pml4_ptr = unsafe { crate::mem::paging::prepare_identity_mapped_pml4() } as usize;
+        let gdtr = crate::mem::gdt::create_simple_gdtr(long_mode);
+
+        // the 32 bit entry runs without paging but needs its boot params and the mode switch below 4G
+        let (pml4_ptr, protected_mode) = if long_mode {
+            println!("Building page tables...");
+            (unsafe { crate::mem::paging::prepare_identity_mapped_pml4() } as usize, None)
+        } else {
+            let trampoline = crate::mem::protected_mode::prepare_trampoline()?;
+            let zero_page = allocate_low_pages(core::mem::size_of::<BootParams>().div_ceil(4096))?;
+            (0, Some((trampoline, zero_page)))
+        };
+
+        // must be allocated last so that it accounts for all memory map entries created by the allocations above
+        let e820_ext = self.reserve_e820_ext()?;
+
+        self.setup_data.link(&mut boot_params);
+        Kernel::check_e820_capacity(&e820_ext)?;
+
+        if dry_run {
+            // the memory map will look a bit different after exit_boot_services but this is as close as we get
+            let mut mmap = boot::memory_map(MemoryType::LOADER_DATA)?;
+            Kernel::set_memory_map(&mut boot_params, &mut mmap, &e820_ext);
+
+            dump::print_boot_params(&boot_params);
+            print_setup_data(&boot_params);
+            if long_mode {
+                println!("Planned entry point: {:#x} (64 bit boot protocol) with page tables at {:#x}", entry_point, pml4_ptr);
+            } else {
+                println!("Planned entry point: {:#x} (32 bit boot protocol)", entry_point);
+            }
+            return Ok(());
+        }
+
+        print_setup_data(&boot_params);
 
         println!("Exiting boot services, bye...");
 
         unsafe {
-            let old_mmap = uefi::boot::exit_boot_services(MemoryType::LOADER_DATA);
+            let mut old_mmap = uefi::boot::exit_boot_services(MemoryType::LOADER_DATA);
+
+            Kernel::set_memory_map(&mut boot_params, &mut old_mmap, &e820_ext);
+
+            if let Some((trampoline, zero_page)) = protected_mode {
+                (zero_page as *mut BootParams).write(boot_params);
+                crate::mem::gdt::load_gdtr(&gdtr);
+
+                let registers = EntryRegisters { eax: 0, ebx: 0, esi: zero_page as u32 };
+                crate::mem::protected_mode::jump_to_protected_mode(trampoline, entry_point as u32, registers);
+            }
 
-            Kernel::set_memory_map(&mut boot_params, &old_mmap);
             crate::mem::gdt::set_gdtr(&gdtr);
 
             Kernel::run(pml4_ptr, entry_point, boot_params);
         }
     }
 
-    fn efi_handover(&mut self, mut boot_params: BootParams) -> SimpleResult<()> {
+    fn efi_handover(&mut self, mut boot_params: BootParams, dry_run: bool) -> SimpleResult<()> {
         println!("Starting using efi handover");
 
         let protected_mode_kernel_addr = self.extract_protected_mode_kernel_to_aligned_address(&mut boot_params);
@@ -203,6 +383,18 @@ impl Kernel {
         // calculating entry point
         let entry_point_efi_64bit = protected_mode_kernel_addr + 0x200 + boot_params.kernel_header.handover_offset as usize;
 
+        // the EFI stub adds its own nodes (e.g. PCI ROMs) to the list
+        self.setup_data.link(&mut boot_params);
+
+        if dry_run {
+            dump::print_boot_params(&boot_params);
+            print_setup_data(&boot_params);
+            println!("Planned entry point: {:#x} (EFI handover protocol)", entry_point_efi_64bit);
+            return Ok(());
+        }
+
+        print_setup_data(&boot_params);
+
         println!("Entering kernel, bye...");
 
         Kernel::jump_to_efi_entry(
@@ -213,22 +405,81 @@ impl Kernel {
         );
     }
 
+    // only returns if something went wrong
     pub fn start(&mut self, cmdline: &str, ramdisk: Option<Vec<u8>>) -> SimpleResult<()> {
+        let result = self.prepare_and_boot(cmdline, ramdisk, false);
+        crate::mem::free_tracked_allocations();
+        result
+    }
+
+    // Does all the preparation for booting without exiting boot services, prints the result and frees everything again.
+    pub fn dry_run(&mut self, cmdline: &str, ramdisk: Option<Vec<u8>>) -> SimpleResult<()> {
+        let result = self.prepare_and_boot(cmdline, ramdisk, true);
+        let freed_pages = crate::mem::free_tracked_allocations();
+        println!("Dry run finished, freed {}", crate::disk::human_readable_size(freed_pages as u64 * 4096).trim());
+        result
+    }
+
+    fn prepare_and_boot(&mut self, cmdline: &str, ramdisk: Option<Vec<u8>>, dry_run: bool) -> SimpleResult<()> {
         // copy kernel header into zero page (boot params)
         let mut boot_params = BootParams::new()?;
-        boot_params.kernel_header = *KernelHeader::new(&self.image)?;
+        boot_params.kernel_header = KernelHeader::from_image(&self.image)?;
+
+        // there is no way to report errors once boot services are gone
+        integrity::check_kernel(&self.image, &boot_params.kernel_header)?;
+        crate::secure_boot::verify_image(&self.image, "kernel")?;
+        boot_params.secure_boot = crate::secure_boot::linux_secure_boot_mode();
+        if let Some(ramdisk) = &ramdisk {
+            println!("Initrd format: {}", integrity::check_initrd(ramdisk)?);
+        }
+
+        if dry_run {
+            if crate::tpm::tpm_present() {
+                println!("Would measure the kernel and initrd into PCR {} and the command line into PCR {}",
+                    crate::tpm::PCR_FILES, crate::tpm::PCR_CMDLINE);
+            }
+        } else {
+            crate::tpm::measure_file(&self.image, "Linux kernel")?;
+            if let Some(ramdisk) = &ramdisk {
+                crate::tpm::measure_file(ramdisk, "Linux initrd")?;
+            }
+            crate::tpm::measure_cmdline(cmdline)?;
+        }
 
         // setting parameters shared by both handover methods
         Kernel::set_cmdline(&mut boot_params, cmdline)?;
-        Kernel::set_ramdisk(&mut boot_params, ramdisk);
+        Kernel::set_ramdisk(&mut boot_params, ramdisk)?;
 
         boot_params.kernel_header.type_of_loader = 0xFF; // custom bootloader
         boot_params.kernel_header.vid_mode = 0xFFFF; // TODO: is this correct?
 
-        if boot_params.kernel_header.xloadflags & 0b1000 == 0 {
-            self.normal_handover(boot_params)
+        // needed by kernels that cannot find the RSDP through the EFI system table (normal handover)
+        boot_params.acpi_rsdp_addr = crate::acpi::rsdp_addr().unwrap_or(0);
+
+        let efi_handover = boot_params.kernel_header.xloadflags & XLF_EFI_HANDOVER_64 != 0;
+
+        // collecting the seed rotates the seed file, so a dry run only tells whether there would be one
+        let seed = if dry_run {
+            if random_seed::seed_available() {
+                println!("Would pass a random seed as {}", if efi_handover { "LINUX_EFI_RANDOM_SEED_TABLE" } else { "SETUP_RNG_SEED" });
+            } else {
+                println!("No EFI_RNG_PROTOCOL and no seed file on the ESP found, would not pass a random seed");
+            }
+            None
         } else {
-            self.efi_handover(boot_params)
+            random_seed::collect_seed()
+        };
+
+        if !efi_handover {
+            if let Some(seed) = &seed {
+                self.setup_data.add(SetupDataEntry::RngSeed(seed))?;
+            }
+            self.normal_handover(boot_params, dry_run)
+        } else {
+            if let Some(seed) = &seed {
+                random_seed::install_efi_seed_table(seed)?;
+            }
+            self.efi_handover(boot_params, dry_run)
         }
     }
 
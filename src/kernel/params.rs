@@ -1,7 +1,5 @@
 extern crate alloc;
 
-use alloc::vec::Vec;
-
 use crate::{mem::allocate_low_pages, simple_error::SimpleResult};
 
 use super::simple_error;
@@ -59,7 +57,8 @@ pub struct BootParams {
     _pad0: u32,
     pub tboot_addr: u64,
     pub ist_info: [u8; 0x10],
-    _pad1: [u8; 0x10],
+    pub acpi_rsdp_addr: u64,
+    _pad1: [u8; 0x08],
     _deprecated: [u8; 0x30],
     pub olpc_ofw_header: [u8; 0x10],
     pub ext_ramdisk_image: u32,
@@ -144,10 +143,20 @@ pub struct E820Entry {
 }
 pub const E820_TYPE_RAM: u32 = 1;
 pub const E820_TYPE_RESERVED: u32 = 2;
-pub const _E820_TYPE_ACPI: u32 = 3;
-pub const _E820_TYPE_NVS: u32 = 4;
-pub const _E820_TYPE_UNUSABLE: u32 = 5;
-pub const _E820_TYPE_PMEM: u32 = 7;
+pub const E820_TYPE_ACPI: u32 = 3;
+pub const E820_TYPE_NVS: u32 = 4;
+pub const E820_TYPE_UNUSABLE: u32 = 5;
+pub const E820_TYPE_PMEM: u32 = 7;
+
+// xloadflags
+pub const XLF_KERNEL_64: u16 = 1 << 0;
+pub const XLF_EFI_HANDOVER_64: u16 = 1 << 3;
+
+// loadflags: the protected-mode code is loaded at 0x100000 (bzImage) instead of 0x10000 (zImage)
+pub const LOADED_HIGH: u8 = 1 << 0;
+
+// the zero page only has room for this many entries, the rest goes into a SETUP_E820_EXT node
+pub const E820_MAX_ENTRIES_ZEROPAGE: usize = 128;
 
 
 impl BootParams {
@@ -162,7 +171,24 @@ impl BootParams {
 
 
 impl KernelHeader {
-    pub fn new(kernel_image: &Vec<u8>) -> SimpleResult<&KernelHeader> {
+    // Copies the header and zeroes the fields that older boot protocol versions do not have yet (they would
+    // contain code otherwise). The header ends where the jump instruction at 0x200 points to.
+    pub fn from_image(kernel_image: &[u8]) -> SimpleResult<KernelHeader> {
+        let mut kernel_header = *KernelHeader::new(kernel_image)?;
+
+        let kernel_header_size = core::mem::size_of::<KernelHeader>();
+        let header_end = 0x202 + kernel_image[0x201] as usize;
+        let valid_len = header_end.saturating_sub(0x1f1).min(kernel_header_size);
+
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(&mut kernel_header as *mut KernelHeader as *mut u8, kernel_header_size)
+        };
+        bytes[valid_len..].fill(0);
+
+        Ok(kernel_header)
+    }
+
+    pub fn new(kernel_image: &[u8]) -> SimpleResult<&KernelHeader> {
         let kernel_header_offset = 0x1f1;
         let kernel_header_size = core::mem::size_of::<KernelHeader>();
 
@@ -0,0 +1,152 @@
+/*
+Early-boot entropy is poor on VMs, so we hand the kernel a random seed. The seed is read from EFI_RNG_PROTOCOL
+and mixed with a seed file on the ESP which is refreshed on every boot. This is similar to what systemd-boot does
+(https://systemd.io/RANDOM_SEEDS/) and we even use the same seed file so both bootloaders can share it.
+The kernel gets the seed as a SETUP_RNG_SEED node (normal handover) or as LINUX_EFI_RANDOM_SEED_TABLE (EFI stub).
+*/
+
+extern crate alloc;
+
+use sha2::{Digest, Sha256};
+use uefi::boot::{self, MemoryType};
+use uefi::proto::media::file::{File, FileAttribute, FileInfo, FileMode};
+use uefi::proto::rng::Rng;
+use uefi::data_types::Align;
+use uefi::runtime::Time;
+use uefi::{guid, println, Guid};
+
+use crate::disk::fs::Filesystem;
+use crate::disk::{open_esp, open_protocol_unsafe};
+use crate::simple_error::{simple_error, SimpleResult};
+
+pub const SEED_SIZE: usize = 32;
+
+const SEED_FILE_DIR: &str = "\\loader";
+const SEED_FILE_PATH: &str = "\\loader\\random-seed";
+
+// https://github.com/torvalds/linux/blob/v6.6/include/linux/efi.h#L420
+static LINUX_EFI_RANDOM_SEED_TABLE_GUID: Guid = guid!("1ce1e5bc-7ceb-42f2-81e5-8aadf180f57b");
+
+// Returns None if neither the RNG protocol nor the seed file is available.
+pub fn collect_seed() -> Option<[u8; SEED_SIZE]> {
+    let rng_seed = read_efi_rng();
+    let file_seed = read_seed_file();
+
+    if rng_seed.is_none() && file_seed.is_none() {
+        println!("No EFI_RNG_PROTOCOL and no seed file on the ESP found, not passing a random seed to the kernel");
+        return None;
+    }
+
+    // the time makes sure that we never derive the same seeds twice, even if there is only the seed file
+    let time = uefi::runtime::get_time().map(|time| alloc::format!("{time}")).unwrap_or_default();
+
+    let mix = |label: &[u8]| -> [u8; SEED_SIZE] {
+        let mut hasher = Sha256::new();
+        hasher.update(label);
+        if let Some(rng_seed) = &rng_seed {
+            hasher.update(rng_seed);
+        }
+        if let Some(file_seed) = &file_seed {
+            hasher.update(file_seed);
+        }
+        hasher.update(time.as_bytes());
+        hasher.finalize().into()
+    };
+
+    // different labels so the new file seed does not reveal the seed we give to the kernel
+    let kernel_seed = mix(b"bs2boot kernel seed");
+    let new_file_seed = mix(b"bs2boot file seed");
+
+    if let Err(err) = write_seed_file(&new_file_seed) {
+        println!("Could not refresh the seed file on the ESP: {err}");
+    }
+
+    println!(
+        "Random seed for the kernel collected (EFI_RNG_PROTOCOL: {}, seed file: {})",
+        rng_seed.is_some(),
+        file_seed.is_some()
+    );
+    Some(kernel_seed)
+}
+
+// for dry runs, does not touch the seed file
+pub fn seed_available() -> bool {
+    read_efi_rng().is_some() || read_seed_file().is_some()
+}
+
+// The EFI stub picks up this table and mixes it with its own entropy.
+pub fn install_efi_seed_table(seed: &[u8; SEED_SIZE]) -> SimpleResult<()> {
+    // struct linux_efi_random_seed { u32 size; u8 bits[]; }
+    let table = boot::allocate_pool(MemoryType::ACPI_RECLAIM, 4 + SEED_SIZE)?.as_ptr();
+
+    unsafe {
+        (table as *mut u32).write_unaligned(SEED_SIZE as u32);
+        core::ptr::copy(seed.as_ptr(), table.add(4), SEED_SIZE);
+
+        boot::install_configuration_table(&LINUX_EFI_RANDOM_SEED_TABLE_GUID, table as *const core::ffi::c_void)?;
+    }
+    Ok(())
+}
+
+fn read_efi_rng() -> Option<[u8; SEED_SIZE]> {
+    let handle = boot::get_handle_for_protocol::<Rng>().ok()?;
+    let mut rng = open_protocol_unsafe::<Rng>(handle).ok()?;
+
+    let mut seed = [0u8; SEED_SIZE];
+    rng.get_rng(None, &mut seed).ok()?;
+    Some(seed)
+}
+
+fn read_seed_file() -> Option<alloc::vec::Vec<u8>> {
+    let seed = open_esp()?.read_file(SEED_FILE_PATH).ok()?;
+
+    if seed.len() < SEED_SIZE {
+        return None;    // too short to contain any meaningful entropy
+    }
+    Some(seed)
+}
+
+fn write_seed_file(seed: &[u8; SEED_SIZE]) -> SimpleResult<()> {
+    let Some(mut esp) = open_esp() else {
+        return Ok(());  // not loaded from a file system
+    };
+
+    let mut root = esp.open_volume()?;
+
+    // creates the directory if it does not exist yet
+    let dir_path = uefi::CString16::try_from(SEED_FILE_DIR).unwrap();
+    root.open(&dir_path, FileMode::CreateReadWrite, FileAttribute::DIRECTORY)?;
+
+    let path = uefi::CString16::try_from(SEED_FILE_PATH).unwrap();
+    let file = root.open(&path, FileMode::CreateReadWrite, FileAttribute::empty())?;
+
+    let Some(mut file) = file.into_regular_file() else {
+        return Ok(());
+    };
+
+    if let Err(err) = file.write(seed) {
+        return simple_error!("{}", err.status());
+    }
+
+    // an older seed file can be longer than the new seed, it is only truncated after the new seed was written
+    // so a failing write never loses the seed
+    let info = file.get_boxed_info::<FileInfo>()?;
+    if info.file_size() > SEED_SIZE as u64 {
+        let mut storage = [0u8; 256];
+        let Some(storage) = FileInfo::align_buf(&mut storage) else {
+            return simple_error!("Could not align the file info buffer");
+        };
+
+        // invalid (zero) times are not changed by set_info
+        let truncated = match FileInfo::new(
+            storage, SEED_SIZE as u64, 0, Time::invalid(), Time::invalid(), Time::invalid(), info.attribute(), info.file_name(),
+        ) {
+            Ok(truncated) => truncated,
+            Err(_) => return simple_error!("Could not create the file info to truncate the seed file"),
+        };
+        file.set_info(truncated)?;
+    }
+
+    file.flush()?;
+    Ok(())
+}
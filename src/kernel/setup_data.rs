@@ -0,0 +1,265 @@
+/*
+The setup_data linked list (boot protocol 2.09+) lets the bootloader pass additional data to the kernel.
+Each node starts with a SetupData header which is directly followed by the payload. The head of the list is
+kernel_header.setup_data and the nodes are chained with their physical addresses.
+https://www.kernel.org/doc/html/v6.6/arch/x86/boot.html (field name: setup_data)
+*/
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use uefi::boot;
+use uefi::println;
+
+use crate::disk::open_protocol_unsafe;
+use crate::mem::allocate_pages;
+use crate::pci::PciIo;
+use crate::simple_error::{simple_error, SimpleResult};
+
+use super::params::BootParams;
+
+// https://github.com/torvalds/linux/blob/v6.6/arch/x86/include/uapi/asm/bootparam.h#L6
+pub const SETUP_E820_EXT: u32 = 1;
+pub const SETUP_DTB: u32 = 2;
+pub const SETUP_PCI: u32 = 3;
+pub const SETUP_EFI: u32 = 4;
+pub const SETUP_APPLE_PROPERTIES: u32 = 5;
+pub const SETUP_JAILHOUSE: u32 = 6;
+pub const SETUP_CC_BLOB: u32 = 7;
+pub const SETUP_IMA: u32 = 8;
+pub const SETUP_RNG_SEED: u32 = 9;
+
+// struct definition here: https://github.com/torvalds/linux/blob/v6.6/arch/x86/include/uapi/asm/bootparam.h#L49
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+pub struct SetupData {
+    pub next: u64,
+    pub typ: u32,
+    pub len: u32,
+}
+
+// header of the payload of SETUP_PCI nodes, the ROM image follows directly after it
+// struct definition here: https://github.com/torvalds/linux/blob/v6.6/arch/x86/include/asm/pci.h#L102 (pci_setup_rom)
+// The kernel's struct is naturally aligned, the padding is explicit so the header can be copied as bytes.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct PciSetupRom {
+    vendor: u16,
+    devid: u16,
+    _pad: u32,
+    pcilen: u64,
+    segment: u64,
+    bus: u64,
+    device: u64,
+    function: u64,
+}
+
+// The node types we know how to build. IMA and CC blobs are opaque to us and passed through as they are.
+pub enum SetupDataEntry<'a> {
+    Dtb(&'a [u8]),
+    PciRom {
+        vendor_id: u16,
+        device_id: u16,
+        segment: u64,
+        bus: u64,
+        device: u64,
+        function: u64,
+        rom: &'a [u8],
+    },
+    RngSeed(&'a [u8]),
+    Ima(&'a [u8]),
+    CcBlob(&'a [u8]),
+}
+
+// A single node in memory. The payload capacity can be larger than its length so nodes can be reserved
+// before exit_boot_services and filled in afterwards (which is needed for SETUP_E820_EXT).
+#[derive(Copy, Clone)]
+pub struct SetupDataNode {
+    addr: usize,
+    capacity: usize,
+}
+
+pub struct SetupDataChain {
+    nodes: Vec<SetupDataNode>,
+}
+
+impl SetupDataNode {
+    // The nodes stay below 4G like everything else we hand to the kernel so that they are reachable
+    // regardless of the kernel's xloadflags.
+    fn allocate(typ: u32, capacity: usize) -> SimpleResult<Self> {
+        let page_count = (core::mem::size_of::<SetupData>() + capacity).div_ceil(4096);
+        let addr = allocate_pages(page_count);
+
+        if addr == 0 {
+            return simple_error!("Could not allocate memory for a setup_data node");
+        }
+
+        let node = SetupDataNode { addr, capacity };
+        node.set_header(SetupData { next: 0, typ, len: 0 });
+        Ok(node)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn header(&self) -> SetupData {
+        unsafe { (self.addr as *const SetupData).read_unaligned() }
+    }
+
+    fn set_header(&self, header: SetupData) {
+        unsafe { (self.addr as *mut SetupData).write_unaligned(header) }
+    }
+
+    pub fn data(&self) -> *mut u8 {
+        (self.addr + core::mem::size_of::<SetupData>()) as *mut u8
+    }
+
+    // does not allocate so this can still be called after exit_boot_services
+    pub fn set_len(&self, len: usize) {
+        let mut header = self.header();
+        header.len = len.min(self.capacity) as u32;
+        self.set_header(header);
+    }
+
+    fn write(&self, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= self.capacity);
+        unsafe { core::ptr::copy(data.as_ptr(), self.data().add(offset), data.len()); }
+    }
+}
+
+impl SetupDataChain {
+    pub fn new() -> Self {
+        SetupDataChain { nodes: Vec::new() }
+    }
+
+    pub fn add(&mut self, entry: SetupDataEntry) -> SimpleResult<()> {
+        let node = match entry {
+            SetupDataEntry::Dtb(dtb) => Self::raw_node(SETUP_DTB, dtb)?,
+            SetupDataEntry::RngSeed(seed) => Self::raw_node(SETUP_RNG_SEED, seed)?,
+            SetupDataEntry::Ima(blob) => Self::raw_node(SETUP_IMA, blob)?,
+            SetupDataEntry::CcBlob(blob) => Self::raw_node(SETUP_CC_BLOB, blob)?,
+            SetupDataEntry::PciRom { vendor_id, device_id, segment, bus, device, function, rom } => {
+                let rom_header = PciSetupRom {
+                    vendor: vendor_id,
+                    devid: device_id,
+                    _pad: 0,
+                    pcilen: rom.len() as u64,
+                    segment,
+                    bus,
+                    device,
+                    function,
+                };
+
+                let header_size = core::mem::size_of::<PciSetupRom>();
+                let node = SetupDataNode::allocate(SETUP_PCI, header_size + rom.len())?;
+
+                let rom_header_bytes = unsafe {
+                    core::slice::from_raw_parts(&rom_header as *const PciSetupRom as *const u8, header_size)
+                };
+                node.write(0, rom_header_bytes);
+                node.write(header_size, rom);
+                node.set_len(header_size + rom.len());
+                node
+            }
+        };
+
+        self.nodes.push(node);
+        Ok(())
+    }
+
+    fn raw_node(typ: u32, data: &[u8]) -> SimpleResult<SetupDataNode> {
+        let node = SetupDataNode::allocate(typ, data.len())?;
+        node.write(0, data);
+        node.set_len(data.len());
+        Ok(node)
+    }
+
+    // reserves a node whose payload is written later through SetupDataNode::data() and set_len()
+    pub fn reserve(&mut self, typ: u32, capacity: usize) -> SimpleResult<SetupDataNode> {
+        let node = SetupDataNode::allocate(typ, capacity)?;
+        self.nodes.push(node);
+        Ok(node)
+    }
+
+    // Links all nodes into the list of the boot params. Nodes that are already in the list stay at its end.
+    pub fn link(&self, boot_params: &mut BootParams) {
+        let mut next = boot_params.kernel_header.setup_data;
+
+        for node in self.nodes.iter().rev() {
+            let mut header = node.header();
+            header.next = next;
+            node.set_header(header);
+            next = node.addr as u64;
+        }
+
+        boot_params.kernel_header.setup_data = next;
+    }
+
+    // adds the option ROMs of all PCI devices like the EFI stub does (the kernel cannot read them itself after exit_boot_services)
+    pub fn add_pci_roms(&mut self) -> SimpleResult<()> {
+        let Ok(handles) = boot::find_handles::<PciIo>() else {
+            return Ok(());  // no PCI devices
+        };
+
+        for handle in handles {
+            let Ok(pci_io) = open_protocol_unsafe::<PciIo>(handle) else {
+                continue;
+            };
+
+            let Some(rom) = pci_io.rom() else {
+                continue;
+            };
+
+            let Some((segment, bus, device, function)) = pci_io.location() else {
+                continue;
+            };
+
+            let Some((vendor_id, device_id)) = pci_io.ids() else {
+                continue;
+            };
+
+            self.add(SetupDataEntry::PciRom { vendor_id, device_id, segment, bus, device, function, rom })?;
+        }
+        Ok(())
+    }
+}
+
+pub fn setup_data_type_as_str(typ: u32) -> &'static str {
+    match typ {
+        SETUP_E820_EXT => "E820_EXT",
+        SETUP_DTB => "DTB",
+        SETUP_PCI => "PCI",
+        SETUP_EFI => "EFI",
+        SETUP_APPLE_PROPERTIES => "APPLE_PROPERTIES",
+        SETUP_JAILHOUSE => "JAILHOUSE",
+        SETUP_CC_BLOB => "CC_BLOB",
+        SETUP_IMA => "IMA",
+        SETUP_RNG_SEED => "RNG_SEED",
+        _ => "Unknown",
+    }
+}
+
+// walks the setup_data list of the boot params and prints every node
+pub fn print_setup_data(boot_params: &BootParams) {
+    let mut addr = boot_params.kernel_header.setup_data;
+
+    if addr == 0 {
+        println!("setup_data: (empty)");
+        return;
+    }
+
+    println!("setup_data:");
+
+    while addr != 0 {
+        let header = unsafe { (addr as *const SetupData).read_unaligned() };
+        let (typ, len) = (header.typ, header.len);
+
+        if typ == SETUP_E820_EXT && len == 0 {
+            println!("  {:#x} {} (filled in after exiting boot services)", addr, setup_data_type_as_str(typ));
+        } else {
+            println!("  {:#x} {} len: {}", addr, setup_data_type_as_str(typ), len);
+        }
+        addr = header.next;
+    }
+}
@@ -1,11 +1,24 @@
 #![no_main]
 #![no_std]
 
+mod acpi;
+mod android;
+mod authenticode;
+mod decompress;
 mod disk;
+mod elf;
+mod elfboot;
 mod kernel;
+mod manifest;
 mod mem;
+mod multiboot2;
+mod password;
+mod pci;
+mod secure_boot;
 mod shell;
 mod simple_error;
+mod tpm;
+mod video;
 
 use shell::*;
 use uefi::{prelude::*, println};
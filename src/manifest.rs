@@ -0,0 +1,145 @@
+/*
+This file implements the hash manifest for boot assets that cannot be signed themselves (initrds, the config, ...).
+The manifest is \bs2boot.sha256 on the ESP in the format of sha256sum, e.g. created with
+`cd /boot/efi && sha256sum bs2boot.cfg EFI/Linux/initrd.img > bs2boot.sha256`. Relative paths are relative to the ESP,
+absolute paths are bs2boot paths like /nvme0n1p2/boot/initrd.img or /by-uuid/<UUID>/boot/initrd.img. Every file read
+through Storage::read_file that the manifest lists (under any name of its partition) has to match its hash, other
+files are not affected.
+If bs2boot was built with MANIFEST_PUBLIC_KEY, the manifest must be signed with the Ed25519 key: the raw 64 byte
+signature of bs2boot.sha256 is read from bs2boot.sha256.sig. Without a valid signature no files can be read.
+*/
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::disk::fs::Filesystem;
+use crate::simple_error::{simple_error, SimpleResult};
+
+pub const MANIFEST_PATH: &str = "\\bs2boot.sha256";
+const SIGNATURE_PATH: &str = "\\bs2boot.sha256.sig";
+
+// the raw 32 byte Ed25519 public key, e.g. Some(*include_bytes!("../keys/manifest.pub"))
+const MANIFEST_PUBLIC_KEY: Option<[u8; 32]> = None;
+
+pub struct Manifest {
+    entries: Vec<(String, [u8; 32])>,
+    pub signed: bool,
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| alloc::format!("{byte:02x}")).collect()
+}
+
+pub fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok()).collect()
+}
+
+// paths on the ESP are compared case-insensitively like FAT does
+fn normalize_esp_path(path: &str) -> String {
+    path.replace('\\', "/").trim_start_matches("./").trim_start_matches('/').to_ascii_lowercase()
+}
+
+impl Manifest {
+    // None if there is no manifest and none is required
+    pub fn load() -> SimpleResult<Option<Manifest>> {
+        let Some(mut esp) = crate::disk::open_esp() else {
+            return Ok(None);
+        };
+
+        let Ok(manifest) = esp.read_file(MANIFEST_PATH) else {
+            if MANIFEST_PUBLIC_KEY.is_some() {
+                return simple_error!("bs2boot requires a signed {MANIFEST_PATH} but it could not be read");
+            }
+            return Ok(None);
+        };
+
+        let signed = match MANIFEST_PUBLIC_KEY {
+            Some(public_key) => {
+                let signature = esp.read_file(SIGNATURE_PATH).unwrap_or_default();
+                Manifest::verify_signature(&public_key, &manifest, &signature)?;
+                true
+            },
+            None => false,
+        };
+
+        let Ok(manifest) = core::str::from_utf8(&manifest) else {
+            return simple_error!("{MANIFEST_PATH} is not valid UTF-8");
+        };
+
+        Ok(Some(Manifest { entries: Manifest::parse(manifest)?, signed }))
+    }
+
+    fn verify_signature(public_key: &[u8; 32], manifest: &[u8], signature: &[u8]) -> SimpleResult<()> {
+        let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+            return simple_error!("The embedded manifest public key is invalid");
+        };
+
+        let Ok(signature) = Signature::from_slice(signature) else {
+            return simple_error!("{SIGNATURE_PATH} is missing or not a 64 byte Ed25519 signature");
+        };
+
+        match key.verify_strict(manifest, &signature) {
+            Ok(()) => Ok(()),
+            Err(_) => simple_error!("The signature of {MANIFEST_PATH} is invalid"),
+        }
+    }
+
+    // lines are "HASH  PATH" or "HASH *PATH" (binary mode), empty lines and comments are ignored
+    fn parse(manifest: &str) -> SimpleResult<Vec<(String, [u8; 32])>> {
+        let mut entries = Vec::new();
+
+        for (idx, line) in manifest.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (hash, path) = line.split_once(' ').unwrap_or((line, ""));
+            let path = path.trim_start().trim_start_matches('*');
+
+            let hash = parse_hex(hash).and_then(|hash| <[u8; 32]>::try_from(hash).ok());
+            let (Some(hash), false) = (hash, path.is_empty()) else {
+                return simple_error!("{MANIFEST_PATH}:{}: invalid line '{line}'", idx + 1);
+            };
+
+            let path = if path.starts_with('/') { path.to_string() } else { normalize_esp_path(path) };
+            entries.push((path, hash));
+        }
+        Ok(entries)
+    }
+
+    pub fn entries(&self) -> &[(String, [u8; 32])] {
+        &self.entries
+    }
+
+    // Relative entries are lowercase and without a leading slash. matches decides if an entry is the file.
+    pub fn lists(&self, matches: impl Fn(&str) -> bool) -> bool {
+        self.entries.iter().any(|(entry, _)| matches(entry))
+    }
+
+    // Returns an error if the manifest lists the file with a different hash. path is only used in the message.
+    pub fn check(&self, path: &str, data: &[u8], matches: impl Fn(&str) -> bool) -> SimpleResult<()> {
+        let expected = self.entries.iter().filter(|(entry, _)| matches(entry));
+
+        let mut hash = None;
+        for (_, expected) in expected {
+            let hash = hash.get_or_insert_with(|| sha256(data));
+            if hash != expected {
+                return simple_error!("{path} does not match its hash in {MANIFEST_PATH}, refusing to use it");
+            }
+        }
+        Ok(())
+    }
+}
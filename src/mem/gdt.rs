@@ -1,4 +1,4 @@
-// This creates and sets a simple GDT containing flat 4G segments as required by the 64bit linux kernel entry point
+// This creates and sets a simple GDT containing flat 4G segments as required by the 64bit and 32bit linux kernel entry points
 // https://wiki.osdev.org/Global_Descriptor_Table
 
 use core::{arch::asm, mem::size_of, slice};
@@ -61,7 +61,8 @@ impl GdtEntry {
     }
 }
 
-pub fn create_simple_gdtr() -> Gdtr {
+// the code segment is a 64 bit one for long mode and a 32 bit one for the 32 bit boot protocol
+pub fn create_simple_gdtr(long_mode: bool) -> Gdtr {
     let gdt_addr = allocate_low_pages(1).unwrap();
 
     let gdt_ptr = gdt_addr as *mut GdtEntry;
@@ -72,7 +73,7 @@ pub fn create_simple_gdtr() -> Gdtr {
     gdt[GDT_ENTRY_BOOT_CS].set_access_flags(
         ACCESS_BYTE_P | ACCESS_BYTE_S | ACCESS_BYTE_E | ACCESS_BYTE_RW | ACCESS_BYTE_A,
     );
-    gdt[GDT_ENTRY_BOOT_CS].set_flags(FLAGS_G | FLAGS_L);
+    gdt[GDT_ENTRY_BOOT_CS].set_flags(if long_mode { FLAGS_G | FLAGS_L } else { FLAGS_G | FLAGS_DB });
 
     gdt[GDT_ENTRY_BOOT_DS].set_base(0);
     gdt[GDT_ENTRY_BOOT_DS].set_limit(u32::MAX);
@@ -90,7 +91,8 @@ pub fn create_simple_gdtr() -> Gdtr {
 
 
 
-pub unsafe fn set_gdtr(gdtr: &Gdtr) {
+// only loads the GDT without reloading the segment registers
+pub unsafe fn load_gdtr(gdtr: &Gdtr) {
     asm!(
         r#"
         lgdt [{}]
@@ -99,6 +101,10 @@ pub unsafe fn set_gdtr(gdtr: &Gdtr) {
         "#,
         in(reg) gdtr,
     );
+}
+
+pub unsafe fn set_gdtr(gdtr: &Gdtr) {
+    load_gdtr(gdtr);
 
     set_cs((GDT_ENTRY_BOOT_CS * 8) as usize);
 
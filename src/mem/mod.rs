@@ -2,6 +2,10 @@
 
 extern crate alloc;
 
+use core::cell::RefCell;
+use core::ptr::NonNull;
+
+use alloc::vec::Vec;
 use uefi::boot::AllocateType;
 use uefi::mem::memory_map::{MemoryMap, MemoryMapMut, MemoryType};
 use uefi::println;
@@ -10,6 +14,34 @@ use crate::simple_error::{simple_error, SimpleResult};
 
 pub(crate) mod gdt;
 pub(crate) mod paging;
+pub(crate) mod protected_mode;
+
+// All pages allocated with the functions below are remembered so they can be freed again if starting a kernel
+// fails or if it was only a dry run. UEFI applications are single threaded so the RefCell is fine.
+struct TrackedAllocations(RefCell<Vec<(usize, usize)>>);
+unsafe impl Sync for TrackedAllocations {}
+
+static TRACKED_ALLOCATIONS: TrackedAllocations = TrackedAllocations(RefCell::new(Vec::new()));
+
+fn track_allocation(addr: usize, count: usize) {
+    TRACKED_ALLOCATIONS.0.borrow_mut().push((addr, count));
+}
+
+// returns the number of freed pages
+pub fn free_tracked_allocations() -> usize {
+    let mut freed_pages = 0;
+
+    for (addr, count) in TRACKED_ALLOCATIONS.0.borrow_mut().drain(..) {
+        let Some(ptr) = NonNull::new(addr as *mut u8) else {
+            continue;
+        };
+
+        if unsafe { uefi::boot::free_pages(ptr, count) }.is_ok() {
+            freed_pages += count;
+        }
+    }
+    freed_pages
+}
 
 pub fn print_memory_map() {
     match uefi::boot::memory_map(MemoryType::LOADER_DATA) {
@@ -39,6 +71,7 @@ pub fn allocate_pages(count: usize) -> usize {
             unsafe {
                 core::ptr::write_bytes(dst.as_mut(), 0, 4096 * count); // zero out pages
             }
+            track_allocation(dst.as_ptr() as usize, count);
             dst.as_ptr() as usize
         }
         Err(err) => {
@@ -58,6 +91,7 @@ pub fn allocate_low_pages(count: usize) -> SimpleResult<usize> {
             unsafe {
                 core::ptr::write_bytes(dst.as_mut(), 0, 4096 * count); // zero out pages
             }
+            track_allocation(dst.as_ptr() as usize, count);
             Ok(dst.as_ptr() as usize)
         }
         Err(err) => {
@@ -82,3 +116,77 @@ pub fn copy_buf_to_aligned_address(buf: &[u8]) -> usize {
 
     rounded_dst
 }
+
+// for kernels that have to be loaded at a fixed address
+pub fn allocate_pages_at(addr: usize, count: usize) -> SimpleResult<()> {
+    match uefi::boot::allocate_pages(AllocateType::Address(addr as u64), MemoryType::LOADER_DATA, count) {
+        Ok(mut dst) => {
+            unsafe {
+                core::ptr::write_bytes(dst.as_mut(), 0, 4096 * count); // zero out pages
+            }
+            track_allocation(addr, count);
+            Ok(())
+        }
+        Err(err) => {
+            simple_error!("Could not allocate {count} pages at {addr:#x} (probably used by the firmware): {err}")
+        }
+    }
+}
+
+// copies the buffer to page aligned memory that ends at or below max_addr
+pub fn copy_buf_below(buf: &[u8], max_addr: usize) -> SimpleResult<usize> {
+    let count = buf.len().div_ceil(4096).max(1);
+
+    let dst = match uefi::boot::allocate_pages(AllocateType::MaxAddress(max_addr as u64), MemoryType::LOADER_DATA, count) {
+        Ok(dst) => dst.as_ptr() as usize,
+        Err(err) => return simple_error!("Could not allocate {count} pages below {max_addr:#x}: {err}"),
+    };
+    track_allocation(dst, count);
+
+    unsafe {
+        core::ptr::copy(buf.as_ptr(), dst as *mut u8, buf.len());
+    }
+    Ok(dst)
+}
+
+// allocates the pages covering [start, end) at exactly that address
+pub fn allocate_range(start: u64, end: u64) -> SimpleResult<()> {
+    if end <= start {
+        return simple_error!("Invalid memory range {start:#x}-{end:#x}");
+    }
+
+    let start_page = start & !0xfff;
+    let page_count = (end.div_ceil(4096) * 4096 - start_page) / 4096;
+
+    check_range_is_free(start_page, start_page + page_count * 4096)?;
+
+    allocate_pages_at(start_page as usize, page_count as usize)
+}
+
+// gives a better error message than the failing allocation if an image wants to be loaded over reserved memory
+fn check_range_is_free(start: u64, end: u64) -> SimpleResult<()> {
+    let mut mmap = uefi::boot::memory_map(MemoryType::LOADER_DATA)?;
+    mmap.sort();
+
+    let mut covered_until = start;
+
+    for entry in mmap.entries() {
+        let (entry_start, entry_end) = (entry.phys_start, entry.phys_start + entry.page_count * 4096);
+        if entry_end <= start || entry_start >= end {
+            continue;
+        }
+
+        if entry.ty != MemoryType::CONVENTIONAL {
+            return simple_error!("Memory range {start:#x}-{end:#x} overlaps {:?} memory at {entry_start:#x}-{entry_end:#x}", entry.ty);
+        }
+        if entry_start > covered_until {
+            break;
+        }
+        covered_until = entry_end;
+    }
+
+    if covered_until < end {
+        return simple_error!("Memory range {start:#x}-{end:#x} is not backed by RAM (from {covered_until:#x})");
+    }
+    Ok(())
+}
@@ -1,4 +1,10 @@
 // This creates a simple, identity-mapped page table.
+// It covers all of physical memory (at least 4G for MMIO) using the largest pages the CPU supports
+// and only uses 4K pages at the end of the range if it is not 2M aligned.
+
+use core::arch::x86_64::__cpuid;
+
+use uefi::mem::memory_map::{MemoryMap, MemoryType};
 
 use crate::mem::*;
 
@@ -9,7 +15,7 @@ const _WRITE_THROUGH: u64 = 1 << 3;
 const _CACHE_DISABLE: u64 = 1 << 4;
 const _ACCESSED: u64 = 1 << 5;
 const _DIRTY: u64 = 1 << 6;
-const _LARGE_PAGE: u64 = 1 << 7;
+const LARGE_PAGE: u64 = 1 << 7;
 const _GLOBAL: u64 = 1 << 8;
 const _EXECUTE_DISABLE: u64 = 1 << 63;
 
@@ -18,13 +24,28 @@ const PD_FLAGS: u64 = PRESENT | RW;
 const PDPT_FLAGS: u64 = PRESENT | RW;
 const PML4_FLAGS: u64 = PRESENT | RW;
 
+const SIZE_4K: u64 = 1 << 12;
+const SIZE_2M: u64 = 1 << 21;
+const SIZE_1G: u64 = 1 << 30;
+const SIZE_512G: u64 = 1 << 39;
+
 #[repr(C, packed)]
 pub struct PageTable {
     entries: [u64; 512],
 }
 
+// counts what we allocated and mapped so we can report the footprint of the page tables
+#[derive(Default)]
+struct IdentityMapStats {
+    tables: usize,
+    pages_1g: usize,
+    pages_2m: usize,
+    pages_4k: usize,
+}
+
 impl PageTable {
-    fn allocate() -> *mut PageTable {
+    fn allocate(stats: &mut IdentityMapStats) -> *mut PageTable {
+        stats.tables += 1;
         allocate_pages(1) as *mut PageTable
     }
 
@@ -33,46 +54,114 @@ impl PageTable {
     }
 }
 
+// CPUID.80000001h:EDX[26]
+fn cpu_supports_1g_pages() -> bool {
+    let max_extended_leaf = __cpuid(0x8000_0000).eax;
+
+    if max_extended_leaf < 0x8000_0001 {
+        return false;
+    }
+
+    __cpuid(0x8000_0001).edx & (1 << 26) != 0
+}
+
+// The end of the highest range in the memory map or of the framebuffer but at least 4G so that
+// all the MMIO below 4G (e.g. local APIC) is mapped.
+fn highest_address_to_map() -> u64 {
+    let mut end = 4 * SIZE_1G;
+
+    if let Ok(mmap) = uefi::boot::memory_map(MemoryType::LOADER_DATA) {
+        for entry in mmap.entries() {
+            end = end.max(entry.phys_start + entry.page_count * SIZE_4K);
+        }
+    }
+
+    if let Some(mut gop) = crate::video::open_gop() {
+        let mut frame_buffer = gop.frame_buffer();
+        end = end.max(frame_buffer.as_mut_ptr() as u64 + frame_buffer.size() as u64);
+    }
+
+    end.div_ceil(SIZE_4K) * SIZE_4K
+}
+
 pub unsafe fn prepare_identity_mapped_pml4() -> *mut PageTable {
-    let pml4_ptr = PageTable::allocate();
+    let end = highest_address_to_map();
+    let use_1g_pages = cpu_supports_1g_pages();
+    let mut stats = IdentityMapStats::default();
+
+    let pml4_ptr = PageTable::allocate(&mut stats);
 
-    (*pml4_ptr).set_entry(0, prepare_identity_mapped_pdpt(0) as u64, PML4_FLAGS);
+    for pml4_idx in 0..end.div_ceil(SIZE_512G) as usize {
+        let address = pml4_idx as u64 * SIZE_512G;
+        let pdpt_ptr = prepare_identity_mapped_pdpt(address, end, use_1g_pages, &mut stats);
+
+        (*pml4_ptr).set_entry(pml4_idx, pdpt_ptr as u64, PML4_FLAGS);
+    }
+
+    println!(
+        "Identity mapped {} using {} 1G, {} 2M and {} 4K pages; the page tables take up {}",
+        crate::disk::human_readable_size(end).trim(),
+        stats.pages_1g,
+        stats.pages_2m,
+        stats.pages_4k,
+        crate::disk::human_readable_size(stats.tables as u64 * SIZE_4K).trim(),
+    );
 
     pml4_ptr
 }
 
-pub unsafe fn prepare_identity_mapped_pdpt(address: usize) -> *mut PageTable {
-    let pdpt_ptr = PageTable::allocate();
+unsafe fn prepare_identity_mapped_pdpt(address: u64, end: u64, use_1g_pages: bool, stats: &mut IdentityMapStats) -> *mut PageTable {
+    let pdpt_ptr = PageTable::allocate(stats);
 
-    for pd_idx in 0..16 {
-        // page tables for 16 GB RAM should be enough, TODO: make this dynamic
-        (*pdpt_ptr).set_entry(
-            pd_idx,
-            prepare_identity_mapped_pd(address + pd_idx * 512 * 512 * 4096) as u64,
-            PDPT_FLAGS,
-        );
+    for pd_idx in 0..512 {
+        let pd_address = address + pd_idx as u64 * SIZE_1G;
+        if pd_address >= end {
+            break;
+        }
+
+        if use_1g_pages && pd_address + SIZE_1G <= end {
+            (*pdpt_ptr).set_entry(pd_idx, pd_address, PDPT_FLAGS | LARGE_PAGE);
+            stats.pages_1g += 1;
+        } else {
+            let pd_ptr = prepare_identity_mapped_pd(pd_address, end, stats);
+            (*pdpt_ptr).set_entry(pd_idx, pd_ptr as u64, PDPT_FLAGS);
+        }
     }
     pdpt_ptr
 }
 
-pub unsafe fn prepare_identity_mapped_pd(address: usize) -> *mut PageTable {
-    let pd_ptr = PageTable::allocate();
+// 2M pages are always available in long mode
+unsafe fn prepare_identity_mapped_pd(address: u64, end: u64, stats: &mut IdentityMapStats) -> *mut PageTable {
+    let pd_ptr = PageTable::allocate(stats);
 
     for pt_idx in 0..512 {
-        (*pd_ptr).set_entry(
-            pt_idx,
-            prepare_identity_mapped_pt(address + pt_idx * 512 * 4096) as u64,
-            PD_FLAGS,
-        );
+        let pt_address = address + pt_idx as u64 * SIZE_2M;
+        if pt_address >= end {
+            break;
+        }
+
+        if pt_address + SIZE_2M <= end {
+            (*pd_ptr).set_entry(pt_idx, pt_address, PD_FLAGS | LARGE_PAGE);
+            stats.pages_2m += 1;
+        } else {
+            let pt_ptr = prepare_identity_mapped_pt(pt_address, end, stats);
+            (*pd_ptr).set_entry(pt_idx, pt_ptr as u64, PD_FLAGS);
+        }
     }
     pd_ptr
 }
 
-pub unsafe fn prepare_identity_mapped_pt(address: usize) -> *mut PageTable {
-    let pt_ptr = PageTable::allocate();
+unsafe fn prepare_identity_mapped_pt(address: u64, end: u64, stats: &mut IdentityMapStats) -> *mut PageTable {
+    let pt_ptr = PageTable::allocate(stats);
 
     for page_idx in 0..512 {
-        (*pt_ptr).set_entry(page_idx, (address + page_idx * 4096) as u64, PT_FLAGS);
+        let page_address = address + page_idx as u64 * SIZE_4K;
+        if page_address >= end {
+            break;
+        }
+
+        (*pt_ptr).set_entry(page_idx, page_address, PT_FLAGS);
+        stats.pages_4k += 1;
     }
     pt_ptr
 }
@@ -0,0 +1,94 @@
+// This switches from long mode back to 32bit protected mode for kernels that only have a 32bit entry point
+// (Linux with boot protocol < 2.12 or without XLF_KERNEL_64, Multiboot2).
+// https://www.kernel.org/doc/html/v6.6/arch/x86/boot.html#bit-boot-protocol
+// Long mode is left by jumping to a 32bit code segment (compatibility mode) and disabling paging. This only works
+// in identity mapped code below 4G, so the 32bit part is copied to a low page before exiting boot services.
+
+use core::arch::{asm, global_asm};
+
+use crate::mem::*;
+
+// selectors of the GDT created by gdt::create_simple_gdtr(false)
+const BOOT_CS: u64 = 0x10;
+
+global_asm!(
+    ".global bs2boot_protected_mode_start",
+    ".global bs2boot_protected_mode_end",
+    "bs2boot_protected_mode_start:",
+    ".code32",
+    // disabling paging also deactivates long mode
+    "mov eax, cr0",
+    "and eax, 0x7fffffff",
+    "mov cr0, eax",
+    // clear EFER.LME
+    "mov ecx, 0xc0000080",
+    "rdmsr",
+    "and eax, 0xfffffeff",
+    "wrmsr",
+    // __BOOT_DS
+    "mov ax, 0x18",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov ss, ax",
+    // the entry point is in edi and the value for eax in ebp, ebx and esi are passed through unchanged
+    "mov ecx, edi",
+    "mov eax, ebp",
+    "xor ebp, ebp",
+    "xor edi, edi",
+    "jmp ecx",
+    ".code64",
+    "bs2boot_protected_mode_end:",
+);
+
+extern "C" {
+    static bs2boot_protected_mode_start: u8;
+    static bs2boot_protected_mode_end: u8;
+}
+
+// Copies the 32bit part to a low page. Must be called before exiting boot services.
+pub fn prepare_trampoline() -> SimpleResult<usize> {
+    let start = &raw const bs2boot_protected_mode_start as usize;
+    let end = &raw const bs2boot_protected_mode_end as usize;
+
+    let trampoline = allocate_low_pages(1)?;
+    unsafe {
+        core::ptr::copy(start as *const u8, trampoline as *mut u8, end - start);
+    }
+    Ok(trampoline)
+}
+
+// Register values at the entry point
+// Linux: esi = boot params, eax = ebx = 0
+// Multiboot2: eax = magic, ebx = boot information
+pub struct EntryRegisters {
+    pub eax: u32,
+    pub ebx: u32,
+    pub esi: u32,
+}
+
+// The GDT with the 32bit code segment must already be loaded (gdt::load_gdtr).
+pub unsafe fn jump_to_protected_mode(trampoline: usize, entry_point: u32, registers: EntryRegisters) -> ! {
+    asm!(
+        "cli",
+        // CR4.PCIDE must be cleared before leaving long mode
+        "mov rax, cr4",
+        "btr rax, 17",
+        "mov cr4, rax",
+        // rbx and rbp cannot be used as operands directly
+        "mov rbx, r8",
+        "mov rbp, r9",
+        // far return into the 32bit code segment
+        "push {cs}",
+        "push rdx",
+        "retfq",
+        cs = const BOOT_CS,
+        in("rdx") trampoline,
+        in("rdi") entry_point as u64,
+        in("rsi") registers.esi as u64,
+        in("r8") registers.ebx as u64,
+        in("r9") registers.eax as u64,
+        options(noreturn),
+    );
+}
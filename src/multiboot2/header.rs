@@ -0,0 +1,137 @@
+// Parsing of the Multiboot2 header which tells us how the image wants to be loaded.
+// https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Header-layout
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+const HEADER_MAGIC: u32 = 0xE85250D6;
+const ARCHITECTURE_I386: u32 = 0;
+
+// the header must be contained completely in the first 32K of the image and be 8 byte aligned
+const HEADER_SEARCH_LIMIT: usize = 32768;
+
+const TAG_END: u16 = 0;
+const TAG_INFORMATION_REQUEST: u16 = 1;
+const TAG_ADDRESS: u16 = 2;
+const TAG_ENTRY_ADDRESS: u16 = 3;
+const TAG_CONSOLE_FLAGS: u16 = 4;
+const TAG_FRAMEBUFFER: u16 = 5;
+const TAG_MODULE_ALIGN: u16 = 6;
+const TAG_EFI_BOOT_SERVICES: u16 = 7;
+const TAG_ENTRY_ADDRESS_EFI32: u16 = 8;
+const TAG_ENTRY_ADDRESS_EFI64: u16 = 9;
+const TAG_RELOCATABLE: u16 = 10;
+
+const TAG_FLAG_OPTIONAL: u16 = 1;
+
+#[derive(Copy, Clone)]
+pub struct AddressTag {
+    pub header_addr: u32,
+    pub load_addr: u32,
+    pub load_end_addr: u32,
+    pub bss_end_addr: u32,
+}
+
+pub struct Header {
+    pub offset: usize,                  // of the header in the image
+    pub requested_tags: Vec<u32>,       // information request tag; only the ones that are not optional
+    pub address: Option<AddressTag>,
+    pub entry_addr: Option<u32>,
+    pub efi64_entry_addr: Option<u32>,
+    pub keep_boot_services: bool,
+    pub framebuffer: Option<(u32, u32, u32)>,   // preferred width, height and depth (0 means no preference)
+}
+
+fn read_u32(image: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u16(image: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(image[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn find_header(image: &[u8]) -> Option<usize> {
+    let limit = image.len().min(HEADER_SEARCH_LIMIT);
+
+    (0..limit.saturating_sub(15)).step_by(8).find(|&offset| {
+        let magic = read_u32(image, offset);
+        let architecture = read_u32(image, offset + 4);
+        let header_length = read_u32(image, offset + 8);
+        let checksum = read_u32(image, offset + 12);
+
+        magic == HEADER_MAGIC
+            && architecture == ARCHITECTURE_I386
+            && magic.wrapping_add(architecture).wrapping_add(header_length).wrapping_add(checksum) == 0
+    })
+}
+
+impl Header {
+    pub fn parse(image: &[u8]) -> SimpleResult<Header> {
+        let Some(offset) = find_header(image) else {
+            return simple_error!("No Multiboot2 header found in the first 32K of the image");
+        };
+
+        let header_length = read_u32(image, offset + 8) as usize;
+        if offset + header_length > image.len() {
+            return simple_error!("The Multiboot2 header is truncated");
+        }
+        let header = &image[offset..offset + header_length];
+
+        let mut parsed = Header {
+            offset,
+            requested_tags: Vec::new(),
+            address: None,
+            entry_addr: None,
+            efi64_entry_addr: None,
+            keep_boot_services: false,
+            framebuffer: None,
+        };
+
+        // the tags start after the 16 byte header and are 8 byte aligned
+        let mut tag_offset = 16;
+        while tag_offset + 8 <= header.len() {
+            let typ = read_u16(header, tag_offset);
+            let flags = read_u16(header, tag_offset + 2);
+            let size = read_u32(header, tag_offset + 4) as usize;
+
+            if size < 8 || tag_offset + size > header.len() {
+                return simple_error!("Multiboot2 header tag {typ} has an invalid size");
+            }
+            let tag = &header[tag_offset..tag_offset + size];
+            let optional = flags & TAG_FLAG_OPTIONAL != 0;
+
+            match typ {
+                TAG_END => break,
+                TAG_INFORMATION_REQUEST if !optional => {
+                    parsed.requested_tags.extend(tag[8..].as_chunks::<4>().0.iter().map(|chunk| u32::from_le_bytes(*chunk)));
+                }
+                TAG_ADDRESS if size >= 24 => {
+                    parsed.address = Some(AddressTag {
+                        header_addr: read_u32(tag, 8),
+                        load_addr: read_u32(tag, 12),
+                        load_end_addr: read_u32(tag, 16),
+                        bss_end_addr: read_u32(tag, 20),
+                    });
+                }
+                TAG_ENTRY_ADDRESS if size >= 12 => parsed.entry_addr = Some(read_u32(tag, 8)),
+                TAG_ENTRY_ADDRESS_EFI64 if size >= 12 => parsed.efi64_entry_addr = Some(read_u32(tag, 8)),
+                TAG_EFI_BOOT_SERVICES => parsed.keep_boot_services = true,
+                TAG_FRAMEBUFFER if size >= 20 => {
+                    parsed.framebuffer = Some((read_u32(tag, 8), read_u32(tag, 12), read_u32(tag, 16)));
+                }
+                // we do not have a text console, modules are always page aligned, the image is loaded at
+                // its preferred address and we are not running on 32 bit EFI
+                TAG_INFORMATION_REQUEST | TAG_CONSOLE_FLAGS | TAG_MODULE_ALIGN | TAG_RELOCATABLE | TAG_ENTRY_ADDRESS_EFI32 => {}
+                _ if optional => {}
+                _ => return simple_error!("Unsupported Multiboot2 header tag {typ}"),
+            }
+
+            tag_offset += size.div_ceil(8) * 8;
+        }
+
+        Ok(parsed)
+    }
+}
@@ -0,0 +1,232 @@
+/*
+This file builds the Multiboot2 boot information (MBI) directly in memory below 4G.
+All tags except the memory map are written before exiting boot services. The memory map tags are appended
+afterwards, so that part must not allocate.
+https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Boot-information-format
+*/
+
+extern crate alloc;
+
+use uefi::boot;
+use uefi::mem::memory_map::{MemoryMap, MemoryMapMut, MemoryMapOwned};
+
+use crate::kernel::Kernel;
+use crate::simple_error::{simple_error, SimpleResult};
+
+// passed in eax (rax for the EFI amd64 entry point)
+pub const BOOTLOADER_MAGIC: u32 = 0x36d76289;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOT_LOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_BASIC_MEMINFO: u32 = 4;
+const TAG_MMAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_EFI64: u32 = 12;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+const TAG_EFI_MMAP: u32 = 17;
+const TAG_EFI_BS: u32 = 18;
+const TAG_EFI64_IH: u32 = 20;
+
+// tags an image may require in its information request tag
+pub const PROVIDED_TAGS: [u32; 12] = [
+    TAG_CMDLINE, TAG_BOOT_LOADER_NAME, TAG_MODULE, TAG_BASIC_MEMINFO, TAG_MMAP, TAG_FRAMEBUFFER,
+    TAG_EFI64, TAG_ACPI_OLD, TAG_ACPI_NEW, TAG_EFI_MMAP, TAG_EFI_BS, TAG_EFI64_IH,
+];
+
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+const MMAP_ENTRY_SIZE: u32 = 24;
+const MMAP_TYPE_AVAILABLE: u32 = 1;    // the types are the same as the E820 ones
+
+pub struct Mbi {
+    addr: usize,
+    capacity: usize,
+    offset: usize,  // where the next tag goes
+}
+
+impl Mbi {
+    pub fn allocate(capacity: usize) -> SimpleResult<Mbi> {
+        let page_count = capacity.div_ceil(4096);
+        let addr = crate::mem::allocate_pages(page_count);
+
+        if addr == 0 {
+            return simple_error!("Could not allocate memory for the Multiboot2 boot information");
+        }
+
+        // the first 8 bytes are total_size and reserved
+        Ok(Mbi { addr, capacity: page_count * 4096, offset: 8 })
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    // always leaves room for the end tag
+    fn push(&mut self, bytes: &[u8]) -> bool {
+        if self.offset + bytes.len() + 8 > self.capacity {
+            return false;
+        }
+
+        unsafe {
+            core::ptr::copy(bytes.as_ptr(), (self.addr + self.offset) as *mut u8, bytes.len());
+        }
+        self.offset += bytes.len();
+        true
+    }
+
+    fn begin_tag(&mut self, typ: u32) -> Option<usize> {
+        let start = self.offset;
+        (self.push(&typ.to_le_bytes()) && self.push(&0u32.to_le_bytes())).then_some(start)
+    }
+
+    // writes the size and pads the tag to 8 bytes (the memory is already zeroed)
+    fn end_tag(&mut self, start: usize) {
+        let size = (self.offset - start) as u32;
+        unsafe { ((self.addr + start + 4) as *mut u32).write_unaligned(size); }
+        self.offset = self.offset.div_ceil(8) * 8;
+    }
+
+    fn add_tag(&mut self, typ: u32, parts: &[&[u8]]) -> SimpleResult<()> {
+        let Some(start) = self.begin_tag(typ) else {
+            return simple_error!("The Multiboot2 boot information is full");
+        };
+
+        for part in parts {
+            if !self.push(part) {
+                self.offset = start;    // drop the incomplete tag
+                return simple_error!("The Multiboot2 boot information is full");
+            }
+        }
+        self.end_tag(start);
+        Ok(())
+    }
+
+    pub fn add_cmdline(&mut self, cmdline: &str) -> SimpleResult<()> {
+        self.add_tag(TAG_CMDLINE, &[cmdline.as_bytes(), &[0]])
+    }
+
+    pub fn add_boot_loader_name(&mut self) -> SimpleResult<()> {
+        self.add_tag(TAG_BOOT_LOADER_NAME, &[b"bs2boot\0"])
+    }
+
+    pub fn add_module(&mut self, start: u32, end: u32, cmdline: &str) -> SimpleResult<()> {
+        self.add_tag(TAG_MODULE, &[&start.to_le_bytes(), &end.to_le_bytes(), cmdline.as_bytes(), &[0]])
+    }
+
+    // nothing is added without a GOP or if the current mode has no framebuffer
+    pub fn add_framebuffer(&mut self) -> SimpleResult<()> {
+        let Some(mut gop) = crate::video::open_gop() else {
+            return Ok(());
+        };
+
+        let mode_info = gop.current_mode_info();
+        let Some(layout) = crate::video::pixel_layout(&mode_info) else {
+            return Ok(());
+        };
+
+        let (width, height) = mode_info.resolution();
+        let addr = gop.frame_buffer().as_mut_ptr() as u64;
+        let pitch = (mode_info.stride() * layout.bits_per_pixel as usize / 8) as u32;
+
+        self.add_tag(TAG_FRAMEBUFFER, &[
+            &addr.to_le_bytes(),
+            &pitch.to_le_bytes(),
+            &(width as u32).to_le_bytes(),
+            &(height as u32).to_le_bytes(),
+            &[layout.bits_per_pixel as u8, FRAMEBUFFER_TYPE_RGB, 0, 0],
+            &[layout.red.0, layout.red.1, layout.green.0, layout.green.1, layout.blue.0, layout.blue.1],
+        ])
+    }
+
+    // copies of the RSDP; the ACPI 1.0 part is always there, the rest only for revision >= 2
+    pub fn add_acpi(&mut self) -> SimpleResult<()> {
+        let (Ok(rsdp), Some(addr)) = (crate::acpi::rsdp(), crate::acpi::rsdp_addr()) else {
+            return Ok(());
+        };
+
+        let rsdp_v1 = unsafe { core::slice::from_raw_parts(addr as *const u8, 20) };
+        self.add_tag(TAG_ACPI_OLD, &[rsdp_v1])?;
+
+        if rsdp.revision >= 2 {
+            let rsdp_v2 = unsafe { core::slice::from_raw_parts(addr as *const u8, rsdp.length as usize) };
+            self.add_tag(TAG_ACPI_NEW, &[rsdp_v2])?;
+        }
+        Ok(())
+    }
+
+    pub fn add_efi(&mut self, keep_boot_services: bool) -> SimpleResult<()> {
+        let system_table = uefi::table::system_table_raw().unwrap().as_ptr() as u64;
+        self.add_tag(TAG_EFI64, &[&system_table.to_le_bytes()])?;
+
+        if keep_boot_services {
+            let image_handle = boot::image_handle().as_ptr() as u64;
+            self.add_tag(TAG_EFI_BS, &[])?;
+            self.add_tag(TAG_EFI64_IH, &[&image_handle.to_le_bytes()])?;
+        }
+        Ok(())
+    }
+
+    // Appends the memory map tags and the end tag. This runs after exit_boot_services so it must not allocate;
+    // entries that do not fit are dropped.
+    pub fn finish(&mut self, mmap: &mut MemoryMapOwned, include_efi_mmap: bool) {
+        mmap.sort();
+
+        let (mem_lower, mem_upper) = basic_meminfo(mmap);
+        let _ = self.add_tag(TAG_BASIC_MEMINFO, &[&mem_lower.to_le_bytes(), &mem_upper.to_le_bytes()]);
+
+        if let Some(start) = self.begin_tag(TAG_MMAP) {
+            self.push(&MMAP_ENTRY_SIZE.to_le_bytes());
+            self.push(&0u32.to_le_bytes());    // entry_version
+
+            for entry in mmap.entries() {
+                let typ = Kernel::e820_type(entry.ty);
+                let size = entry.page_count * 4096;
+
+                if !(self.push(&entry.phys_start.to_le_bytes()) && self.push(&size.to_le_bytes())
+                    && self.push(&typ.to_le_bytes()) && self.push(&0u32.to_le_bytes())) {
+                    break;
+                }
+            }
+            // a partially written entry is cut off
+            self.offset -= (self.offset - start).saturating_sub(16) % MMAP_ENTRY_SIZE as usize;
+            self.end_tag(start);
+        }
+
+        if include_efi_mmap {
+            let meta = mmap.meta();
+            let _ = self.add_tag(TAG_EFI_MMAP, &[
+                &(meta.desc_size as u32).to_le_bytes(),
+                &meta.desc_version.to_le_bytes(),
+                &mmap.buffer()[..meta.map_size],
+            ]);
+        }
+
+        unsafe {
+            ((self.addr + self.offset) as *mut u32).write(TAG_END);
+            ((self.addr + self.offset + 4) as *mut u32).write(8);
+            (self.addr as *mut u32).write((self.offset + 8) as u32);
+        }
+    }
+}
+
+// KiB of RAM starting at 0 (at most 640K) and at 1M
+fn basic_meminfo(mmap: &MemoryMapOwned) -> (u32, u32) {
+    let contiguous_ram_end = |start: u64| {
+        let mut end = start;
+        for entry in mmap.entries() {
+            let entry_end = entry.phys_start + entry.page_count * 4096;
+            if Kernel::e820_type(entry.ty) == MMAP_TYPE_AVAILABLE && entry.phys_start <= end && entry_end > end {
+                end = entry_end;
+            }
+        }
+        end
+    };
+
+    let mem_lower = contiguous_ram_end(0).min(640 * 1024) / 1024;
+    let mem_upper = (contiguous_ram_end(0x100000) - 0x100000) / 1024;
+
+    (mem_lower as u32, mem_upper.min(u32::MAX as u64) as u32)
+}
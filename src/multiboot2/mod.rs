@@ -0,0 +1,208 @@
+/*
+This file contains the logic to load and start Multiboot2 images (hobby kernels, Xen).
+The image is loaded with the address tag of its header or from its ELF program headers. It is started in 32 bit
+protected mode after exiting boot services, or with boot services still running at the EFI amd64 entry point if
+the header has both the EFI boot services tag and the EFI amd64 entry address tag.
+https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html
+*/
+
+extern crate alloc;
+
+mod header;
+mod mbi;
+
+use core::arch::asm;
+
+use alloc::{format, string::String, vec::Vec};
+use uefi::boot::{self, MemoryType};
+use uefi::mem::memory_map::MemoryMap;
+use uefi::println;
+
+use crate::elf::{Elf, EM_386, EM_X86_64};
+use crate::mem::protected_mode::EntryRegisters;
+use crate::simple_error::{simple_error, SimpleResult};
+
+use self::header::{AddressTag, Header};
+use self::mbi::{Mbi, BOOTLOADER_MAGIC, PROVIDED_TAGS};
+
+struct Module {
+    data: Vec<u8>,
+    cmdline: String,
+}
+
+pub struct Multiboot2 {
+    image: Vec<u8>,
+    header: Header,
+    modules: Vec<Module>,
+}
+
+impl Multiboot2 {
+    pub fn new(image: Vec<u8>) -> SimpleResult<Self> {
+        crate::secure_boot::refuse_unsigned_format("Multiboot2")?;
+
+        let header = Header::parse(&image)?;
+
+        if let Some(tag) = header.requested_tags.iter().find(|tag| !PROVIDED_TAGS.contains(tag)) {
+            return simple_error!("The image requires Multiboot2 information tag {tag} which is not supported");
+        }
+
+        Ok(Multiboot2 { image, header, modules: Vec::new() })
+    }
+
+    // modules are only copied to their final location when the image is started
+    pub fn add_module(&mut self, data: Vec<u8>, cmdline: &str) {
+        self.modules.push(Module { data, cmdline: cmdline.into() });
+    }
+
+    // only returns if something went wrong
+    pub fn start(&mut self, cmdline: &str) -> SimpleResult<()> {
+        let result = self.load_and_boot(cmdline);
+        crate::mem::free_tracked_allocations();
+        result
+    }
+
+    // returns the entry point of the ELF file if the image is one
+    fn load_image(&self) -> SimpleResult<Option<u64>> {
+        if let Some(address) = self.header.address {
+            self.load_with_address_tag(address)?;
+            return Ok(None);
+        }
+
+        let elf = Elf::parse(&self.image)?;
+
+        if elf.machine != EM_386 && elf.machine != EM_X86_64 {
+            return simple_error!("The image is an ELF file for an unsupported architecture ({:#x})", elf.machine);
+        }
+
+        elf.load()?;
+
+        println!("Loaded {} segments of the ELF{} image", elf.segments.len(), if elf.is_64bit { 64 } else { 32 });
+        Ok(Some(elf.entry))
+    }
+
+    // https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#The-address-tag-of-Multiboot2-header
+    fn load_with_address_tag(&self, address: AddressTag) -> SimpleResult<()> {
+        let AddressTag { header_addr, load_addr, load_end_addr, bss_end_addr } = address;
+
+        // the header is at header_addr in memory, so this is where loading starts in the file
+        let Some(file_offset) = header_addr.checked_sub(load_addr).and_then(|delta| self.header.offset.checked_sub(delta as usize)) else {
+            return simple_error!("Invalid Multiboot2 address tag (load_addr {load_addr:#x}, header_addr {header_addr:#x})");
+        };
+
+        let load_end = match load_end_addr {
+            0 => load_addr as usize + self.image.len() - file_offset,
+            load_end_addr => load_end_addr as usize,
+        };
+        let bss_end = (bss_end_addr as usize).max(load_end);
+
+        let Some(data) = load_end.checked_sub(load_addr as usize).and_then(|len| self.image.get(file_offset..file_offset + len)) else {
+            return simple_error!("The Multiboot2 address tag points beyond the end of the image");
+        };
+
+        crate::mem::allocate_range(load_addr as u64, bss_end as u64)?;
+        unsafe {
+            core::ptr::copy(data.as_ptr(), load_addr as *mut u8, data.len());
+        }
+
+        println!("Loaded image to {:#x}-{:#x}", load_addr, bss_end);
+        Ok(())
+    }
+
+    fn mbi_capacity(&self, cmdline: &str) -> SimpleResult<usize> {
+        const FIXED_TAGS_SIZE: usize = 4096;    // name, framebuffer, ACPI, EFI, ...
+        const SLACK_ENTRIES: usize = 64;        // the memory map may still grow until exit_boot_services
+
+        let mmap = boot::memory_map(MemoryType::LOADER_DATA)?;
+        let mmap_entry_size = 24 + mmap.meta().desc_size;
+
+        let modules_size: usize = self.modules.iter().map(|module| 24 + module.cmdline.len()).sum();
+
+        Ok(FIXED_TAGS_SIZE + cmdline.len() + modules_size + (mmap.len() + SLACK_ENTRIES) * mmap_entry_size)
+    }
+
+    // measured like the kernel and initrd of a Linux boot
+    fn measure(&self, cmdline: &str) -> SimpleResult<()> {
+        crate::tpm::measure_file(&self.image, "Multiboot2 image")?;
+        crate::tpm::measure_cmdline(cmdline)?;
+
+        for module in &self.modules {
+            crate::tpm::measure_file(&module.data, "Multiboot2 module")?;
+            crate::tpm::measure_cmdline(&module.cmdline)?;
+        }
+        Ok(())
+    }
+
+    fn load_and_boot(&mut self, cmdline: &str) -> SimpleResult<()> {
+        if let Some((width, height, _)) = self.header.framebuffer {
+            if width != 0 && height != 0 {
+                if let Err(err) = crate::video::set_mode(&format!("{width}x{height}")) {
+                    println!("Could not set the preferred graphics mode {width}x{height}: {err}");
+                }
+            }
+        }
+
+        self.measure(cmdline)?;
+
+        // the image has fixed addresses so it is loaded before anything else is allocated
+        let elf_entry = self.load_image()?;
+
+        let keep_boot_services = self.header.keep_boot_services && self.header.efi64_entry_addr.is_some();
+
+        let mut mbi = Mbi::allocate(self.mbi_capacity(cmdline)?)?;
+        mbi.add_cmdline(cmdline)?;
+        mbi.add_boot_loader_name()?;
+
+        for module in &self.modules {
+            let start = crate::mem::copy_buf_below(&module.data, u32::MAX as usize)?;
+            mbi.add_module(start as u32, (start + module.data.len()) as u32, &module.cmdline)?;
+        }
+
+        mbi.add_framebuffer()?;
+        mbi.add_acpi()?;
+        mbi.add_efi(keep_boot_services)?;
+
+        if let (true, Some(entry_point)) = (keep_boot_services, self.header.efi64_entry_addr) {
+            let mut mmap = boot::memory_map(MemoryType::LOADER_DATA)?;
+            mbi.finish(&mut mmap, false);
+
+            println!("Entering kernel at {:#x} with boot services, bye...", entry_point);
+            unsafe { Multiboot2::jump_to_efi64_entry(entry_point as usize, mbi.addr()) };
+        }
+
+        let Some(entry_point) = self.header.entry_addr.map(u64::from).or(elf_entry) else {
+            return simple_error!("The image has neither an entry address tag nor an ELF entry point");
+        };
+
+        if entry_point > u32::MAX as u64 {
+            return simple_error!("The entry point {entry_point:#x} is not reachable from 32 bit protected mode");
+        }
+
+        let gdtr = crate::mem::gdt::create_simple_gdtr(false);
+        let trampoline = crate::mem::protected_mode::prepare_trampoline()?;
+
+        println!("Exiting boot services, bye...");
+
+        unsafe {
+            let mut mmap = boot::exit_boot_services(MemoryType::LOADER_DATA);
+            mbi.finish(&mut mmap, true);
+
+            crate::mem::gdt::load_gdtr(&gdtr);
+
+            let registers = EntryRegisters { eax: BOOTLOADER_MAGIC, ebx: mbi.addr() as u32, esi: 0 };
+            crate::mem::protected_mode::jump_to_protected_mode(trampoline, entry_point as u32, registers);
+        }
+    }
+
+    // the EFI amd64 entry point is called in long mode like an EFI application
+    unsafe fn jump_to_efi64_entry(entry_point: usize, mbi: usize) -> ! {
+        asm!(
+            // rbx cannot be used as an operand directly
+            "mov rbx, rcx",
+            "jmp rdx",
+            in("rax") BOOTLOADER_MAGIC as u64,
+            in("rcx") mbi,
+            in("rdx") entry_point,
+            options(noreturn),
+        );
+    }
+}
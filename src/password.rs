@@ -0,0 +1,49 @@
+/*
+This file checks the shell password. The config only contains a salted PBKDF2-HMAC-SHA256 hash in the format
+pbkdf2-sha256$ITERATIONS$SALT-HEX$HASH-HEX, e.g. created with
+python3 -c "import hashlib,os;s=os.urandom(16);print('pbkdf2-sha256\$100000\$'+s.hex()+'\$'+hashlib.pbkdf2_hmac('sha256',b'PASSWORD',s,100000).hex())"
+*/
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use sha2::Sha256;
+
+use crate::manifest::parse_hex;
+use crate::simple_error::{simple_error, SimpleResult};
+
+const PREFIX: &str = "pbkdf2-sha256";
+
+pub struct PasswordHash {
+    iterations: u32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl PasswordHash {
+    pub fn parse(string: &str) -> SimpleResult<PasswordHash> {
+        let parts: Vec<&str> = string.split('$').collect();
+
+        let [PREFIX, iterations, salt, hash] = parts[..] else {
+            return simple_error!("The password hash must have the format {PREFIX}$ITERATIONS$SALT$HASH");
+        };
+
+        let (Ok(iterations), Some(salt), Some(hash)) = (iterations.parse(), parse_hex(salt), parse_hex(hash)) else {
+            return simple_error!("The password hash contains an invalid number or hex string");
+        };
+
+        if iterations == 0 || salt.is_empty() || hash.is_empty() {
+            return simple_error!("The password hash needs at least one iteration, a salt and a hash");
+        }
+
+        Ok(PasswordHash { iterations, salt, hash })
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        let mut hash = alloc::vec![0u8; self.hash.len()];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &self.salt, self.iterations, &mut hash);
+
+        // compare in constant time
+        hash.iter().zip(&self.hash).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
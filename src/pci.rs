@@ -0,0 +1,67 @@
+// This file contains what we need from the PCI devices, the kernel setup data passes their option ROMs and block
+// devices are named by the kind of their PCI device.
+
+use core::ffi::c_void;
+
+use uefi::proto::unsafe_protocol;
+use uefi::Status;
+
+// Minimal definition of EFI_PCI_IO_PROTOCOL because the uefi crate does not have one. We only need
+// Pci.Read, GetLocation and the ROM image, all other functions are left as opaque pointers.
+// https://uefi.org/specs/UEFI/2.10/14_Protocols_PCI_Bus_Support.html#efi-pci-io-protocol
+#[repr(C)]
+#[unsafe_protocol("4cf5b200-68b8-4ca5-9eec-b23e3f50029a")]
+pub struct PciIo {
+    poll_mem: *const c_void,
+    poll_io: *const c_void,
+    mem: [*const c_void; 2],
+    io: [*const c_void; 2],
+    pci_read: unsafe extern "efiapi" fn(this: *const PciIo, width: u32, offset: u32, count: usize, buffer: *mut c_void) -> Status,
+    pci_write: *const c_void,
+    copy_mem: *const c_void,
+    map: *const c_void,
+    unmap: *const c_void,
+    allocate_buffer: *const c_void,
+    free_buffer: *const c_void,
+    flush: *const c_void,
+    get_location: unsafe extern "efiapi" fn(this: *const PciIo, segment: *mut usize, bus: *mut usize, device: *mut usize, function: *mut usize) -> Status,
+    attributes: *const c_void,
+    get_bar_attributes: *const c_void,
+    set_bar_attributes: *const c_void,
+    rom_size: u64,
+    rom_image: *const u8,
+}
+
+impl PciIo {
+    pub fn rom(&self) -> Option<&[u8]> {
+        if self.rom_size == 0 || self.rom_image.is_null() {
+            return None;
+        }
+        Some(unsafe { core::slice::from_raw_parts(self.rom_image, self.rom_size as usize) })
+    }
+
+    pub fn location(&self) -> Option<(u64, u64, u64, u64)> {
+        let (mut segment, mut bus, mut device, mut function) = (0, 0, 0, 0);
+        let status = unsafe { (self.get_location)(self, &mut segment, &mut bus, &mut device, &mut function) };
+
+        if status.is_success() {
+            Some((segment as u64, bus as u64, device as u64, function as u64))
+        } else {
+            None
+        }
+    }
+
+    // vendor and device id are the first two 16 bit words in the configuration space
+    pub fn ids(&self) -> Option<(u16, u16)> {
+        const PCI_IO_WIDTH_UINT16: u32 = 1;
+
+        let mut ids = [0u16; 2];
+        let status = unsafe { (self.pci_read)(self, PCI_IO_WIDTH_UINT16, 0, 2, ids.as_mut_ptr() as *mut c_void) };
+
+        if status.is_success() {
+            Some((ids[0], ids[1]))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,177 @@
+/*
+This file contains the Secure Boot checks. Under Secure Boot bs2boot is usually started by shim, which installs
+SHIM_LOCK_PROTOCOL so later stages can verify images against db and the Machine Owner Keys (MOK) without going
+through LoadImage. Without shim the Authenticode signature is checked by bs2boot itself against db and the
+certificates compiled into bs2boot. Images that cannot be verified are refused while Secure Boot is enabled or if
+bs2boot was built with its own certificates.
+LoadImage checks images against db once more, which fails for images that are only trusted through a MOK or the
+embedded certificates. So verified EFI applications are loaded while the firmware's security protocols are hooked to
+let exactly that buffer through, like systemd-boot does it.
+https://github.com/rhboot/shim/blob/main/README.md
+*/
+
+extern crate alloc;
+
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use uefi::boot::LoadImageSource;
+use uefi::proto::device_path::DevicePath;
+use uefi::proto::unsafe_protocol;
+use uefi::runtime::{self, VariableVendor};
+use uefi::{boot, cstr16, println, Handle, Status};
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+// values of boot_params.secure_boot (enum efi_secureboot_mode in the kernel)
+pub const LINUX_SECUREBOOT_MODE_DISABLED: u8 = 2;
+pub const LINUX_SECUREBOOT_MODE_ENABLED: u8 = 3;
+
+#[repr(C)]
+#[unsafe_protocol("605dab50-e046-4300-abb6-3dd810dd8b23")]
+struct ShimLock {
+    verify: unsafe extern "efiapi" fn(buffer: *const c_void, size: u32) -> Status,
+    hash: *const c_void,
+    context: *const c_void,
+}
+
+type FileAuthenticationState = unsafe extern "efiapi" fn(this: *const SecurityArch, status: u32, file: *const c_void) -> Status;
+type FileAuthentication = unsafe extern "efiapi" fn(
+    this: *const Security2Arch, file: *const c_void, buffer: *const c_void, size: usize, boot_policy: bool,
+) -> Status;
+
+#[repr(C)]
+#[unsafe_protocol("a46423e3-4617-49f1-b9ff-d1bfa9115839")]
+struct SecurityArch {
+    file_authentication_state: FileAuthenticationState,
+}
+
+#[repr(C)]
+#[unsafe_protocol("94ab2f58-1438-4ef1-9152-18941a3a0e68")]
+struct Security2Arch {
+    file_authentication: FileAuthentication,
+}
+
+// the buffer that is let through and the original functions while the hooks are installed
+static VERIFIED_BUFFER: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_FILE_AUTHENTICATION_STATE: AtomicUsize = AtomicUsize::new(0);
+static ORIGINAL_FILE_AUTHENTICATION: AtomicUsize = AtomicUsize::new(0);
+
+// the old protocol only gets the device path, but only our image is loaded while the hook is installed
+unsafe extern "efiapi" fn file_authentication_state_hook(this: *const SecurityArch, status: u32, file: *const c_void) -> Status {
+    if VERIFIED_BUFFER.load(Ordering::Relaxed) != 0 {
+        return Status::SUCCESS;
+    }
+
+    let original: FileAuthenticationState = unsafe { core::mem::transmute(ORIGINAL_FILE_AUTHENTICATION_STATE.load(Ordering::Relaxed)) };
+    unsafe { original(this, status, file) }
+}
+
+unsafe extern "efiapi" fn file_authentication_hook(
+    this: *const Security2Arch, file: *const c_void, buffer: *const c_void, size: usize, boot_policy: bool,
+) -> Status {
+    if !buffer.is_null() && buffer as usize == VERIFIED_BUFFER.load(Ordering::Relaxed) {
+        return Status::SUCCESS;
+    }
+
+    let original: FileAuthentication = unsafe { core::mem::transmute(ORIGINAL_FILE_AUTHENTICATION.load(Ordering::Relaxed)) };
+    unsafe { original(this, file, buffer, size, boot_policy) }
+}
+
+// the SecureBoot variable is 1 if Secure Boot is enabled and the platform is in user mode
+pub fn secure_boot_enabled() -> bool {
+    let mut buf = [0u8; 1];
+
+    match runtime::get_variable(cstr16!("SecureBoot"), &VariableVendor::GLOBAL_VARIABLE, &mut buf) {
+        Ok((value, _)) => value == [1],
+        Err(_) => false,
+    }
+}
+
+pub fn linux_secure_boot_mode() -> u8 {
+    if secure_boot_enabled() {
+        LINUX_SECUREBOOT_MODE_ENABLED
+    } else {
+        LINUX_SECUREBOOT_MODE_DISABLED
+    }
+}
+
+pub fn verification_required() -> bool {
+    secure_boot_enabled() || crate::authenticode::has_embedded_certificates()
+}
+
+// None if bs2boot was not started by shim
+fn open_shim_lock() -> Option<boot::ScopedProtocol<ShimLock>> {
+    let handle = boot::get_handle_for_protocol::<ShimLock>().ok()?;
+    crate::disk::open_protocol_unsafe::<ShimLock>(handle).ok()
+}
+
+fn verify_with_shim(shim_lock: &ShimLock, image: &[u8], description: &str) -> SimpleResult<()> {
+    let Ok(size) = u32::try_from(image.len()) else {
+        return simple_error!("The {description} is too large to be verified by shim");
+    };
+
+    match unsafe { (shim_lock.verify)(image.as_ptr().cast(), size) } {
+        Status::SUCCESS => Ok(()),
+        status => simple_error!("Secure Boot verification of the {description} failed ({status}), refusing to start it"),
+    }
+}
+
+// for image formats that cannot be signed (Multiboot2, plain ELF)
+pub fn refuse_unsigned_format(description: &str) -> SimpleResult<()> {
+    if verification_required() {
+        return simple_error!("Signature verification is required and {description} images cannot be signed, refusing to start it");
+    }
+    Ok(())
+}
+
+// does nothing if verification is not required
+pub fn verify_image(image: &[u8], description: &str) -> SimpleResult<()> {
+    if !verification_required() {
+        return Ok(());
+    }
+
+    if let Some(shim_lock) = open_shim_lock() {
+        verify_with_shim(&shim_lock, image, description)?;
+        println!("The {description} was verified by shim");
+        return Ok(());
+    }
+
+    match crate::authenticode::verify(image, false) {
+        Ok(()) => {
+            println!("The {description} has a trusted Authenticode signature");
+            Ok(())
+        },
+        Err(err) => simple_error!("Verification of the {description} failed ({err}), refusing to start it"),
+    }
+}
+
+// Loads an image that was verified with verify_image, the firmware's own check is skipped for this buffer only.
+pub fn load_verified_image(image: &[u8], file_path: Option<&DevicePath>) -> uefi::Result<Handle> {
+    let mut security = boot::get_handle_for_protocol::<SecurityArch>().ok()
+        .and_then(|handle| crate::disk::open_protocol_unsafe::<SecurityArch>(handle).ok());
+    let mut security2 = boot::get_handle_for_protocol::<Security2Arch>().ok()
+        .and_then(|handle| crate::disk::open_protocol_unsafe::<Security2Arch>(handle).ok());
+
+    VERIFIED_BUFFER.store(image.as_ptr() as usize, Ordering::Relaxed);
+    if let Some(security) = &mut security {
+        ORIGINAL_FILE_AUTHENTICATION_STATE.store(security.file_authentication_state as usize, Ordering::Relaxed);
+        security.file_authentication_state = file_authentication_state_hook;
+    }
+    if let Some(security2) = &mut security2 {
+        ORIGINAL_FILE_AUTHENTICATION.store(security2.file_authentication as usize, Ordering::Relaxed);
+        security2.file_authentication = file_authentication_hook;
+    }
+
+    let result = boot::load_image(boot::image_handle(), LoadImageSource::FromBuffer { buffer: image, file_path });
+
+    if let Some(security) = &mut security {
+        security.file_authentication_state = unsafe { core::mem::transmute::<usize, FileAuthenticationState>(ORIGINAL_FILE_AUTHENTICATION_STATE.load(Ordering::Relaxed)) };
+    }
+    if let Some(security2) = &mut security2 {
+        security2.file_authentication = unsafe { core::mem::transmute::<usize, FileAuthentication>(ORIGINAL_FILE_AUTHENTICATION.load(Ordering::Relaxed)) };
+    }
+    VERIFIED_BUFFER.store(0, Ordering::Relaxed);
+
+    result
+}
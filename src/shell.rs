@@ -3,6 +3,7 @@ This file contains the shell implementation. The shell calls all the other code
 It reads and parses the keyboard input and executes the commands which are plain Rust functions.
 Paths include the partitions they are on so we simulate all partitions being "mounted" at
 the root directory with the same name as the partition, e.g. /sda1 /sda2 /sdb1 etc.
+The config file is just a list of shell commands (one per line) that are executed at startup.
 */
 
 extern crate alloc;
@@ -17,8 +18,11 @@ use regex::Regex;
 
 use crate::{
     disk::{
-        fs::{FileError, FsPath}, Storage, StorageDevice
+        fs::{FileError, Filesystem, FsPath, BY_DIRECTORIES}, Storage, StorageDevice
     },
+    kernel::setup_data::SetupDataEntry,
+    manifest::MANIFEST_PATH,
+    password::PasswordHash,
     simple_error::{simple_error, SimpleResult},
 };
 
@@ -29,19 +33,43 @@ macro_rules! Char16 {
     }};
 }
 
+// on the partition this bootloader was loaded from
+const CONFIG_PATH: &str = "\\bs2boot.cfg";
+
+// the only commands that can be typed while the shell is locked by a password
+const UNLOCKED_COMMANDS: &[&str] = &["help", "exit", "clear", "quickstart", "quickstart_options", "unlock"];
+
+// commands that can boot arbitrary files or add entries, they cannot be typed in lockdown mode
+const LOCKDOWN_COMMANDS: &[&str] = &["runefi", "runkernel", "runmultiboot2", "runxen", "runelf", "runandroid", "entry"];
+
 pub struct Shell {
     cmd_history_idx: usize,
     cmd_history: Vec<String>,
     cwd: FsPath,
     exit: bool,
     quickstart_options: Vec<QuickstartOption>,
-    storage: Storage
+    storage: Storage,
+    password: Option<PasswordHash>,     // set by the config
+    password_required: bool,            // the config was rejected or its password could not be parsed
+    unlocked: bool,
+    lockdown: bool,                     // enabled under Secure Boot
+}
+
+// arguments of a command split into positional arguments, "--option VALUE" pairs and "--flag"s
+struct CommandArgs {
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+    flags: Vec<String>,
 }
 
-// chainloading .efi or loading a linux kernel
+// chainloading .efi, loading a linux kernel (optionally as Xen dom0 or from an Android boot image) or an entry
+// from the config file
 pub enum QuickstartOption {
     EFI { full_path: FsPath },
     Kernel { kernel_path: FsPath, cmdline: String, ramdisk_path: Option<FsPath> },
+    Android { boot_path: FsPath, vendor_boot_path: Option<FsPath> },
+    Xen { xen_path: FsPath, kernel_path: FsPath, cmdline: String, ramdisk_path: Option<FsPath> },
+    Entry { program: String, args: Vec<String> },
 }
 
 impl Shell {
@@ -53,6 +81,10 @@ impl Shell {
             exit: false,
             quickstart_options: Vec::new(),
             storage: Storage::new().expect("Could not initialize storage"),
+            password: None,
+            password_required: false,
+            unlocked: false,
+            lockdown: crate::secure_boot::secure_boot_enabled(),
         };
 
         shell.quickstart_options = shell.find_quickstart_options().unwrap_or_else(|_| Vec::new());
@@ -60,10 +92,19 @@ impl Shell {
     }
 
     pub fn enter(&mut self) {
+        self.run_config();
+
         let _ = self.help();
         println!();
         let _ = self.quickstart_options();
 
+        if self.locked() {
+            println!("\nThe shell is locked. Boot a quickstart option or type 'unlock'.");
+        }
+        if self.lockdown {
+            println!("\nSecure Boot is enabled, only quickstart options can be booted (lockdown mode).");
+        }
+
         let _ = uefi::system::with_stdout(|stdout| stdout.enable_cursor(true));
 
         // REPL loop
@@ -74,6 +115,60 @@ impl Shell {
         }
     }
 
+    // Executes the commands in the config file. Empty lines and lines starting with # are ignored.
+    // A rejected config could have contained a password, so the shell stays locked then.
+    fn run_config(&mut self) {
+        let Some(mut esp) = crate::disk::open_esp() else {
+            return;
+        };
+
+        let config = esp.read_file(CONFIG_PATH);
+
+        let manifest_check = match (self.storage.esp_partition_name(), self.storage.manifest()) {
+            (Ok(Some(esp)), _) => {
+                let mut path = FsPath::new();
+                path.push(esp).push(CONFIG_PATH.trim_start_matches('\\'));
+                self.storage.check_manifest(&path, config.as_deref().ok())
+            },
+            (Ok(None), Ok(None)) => Ok(()),
+            (Ok(None), Ok(Some(_))) => simple_error!("the ESP was not found, it cannot be checked against the manifest"),
+            (Err(err), _) | (_, Err(err)) => Err(err),
+        };
+        if let Err(err) = manifest_check {
+            println!("Ignoring {CONFIG_PATH}: {err}");
+            self.password_required = true;
+            return;
+        }
+
+        let Ok(config) = config else {
+            return; // there is no config file
+        };
+
+        if let Err(err) = crate::tpm::measure_file(&config, CONFIG_PATH) {
+            println!("{err}");
+        }
+
+        let Ok(config) = core::str::from_utf8(&config) else {
+            println!("{CONFIG_PATH} is not valid UTF-8, ignoring it.");
+            self.password_required = true;
+            return;
+        };
+
+        for line in config.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some((program, args)) = self.parse_command(line) {
+                if let Err(error) = self.execute_command(&program, args) {
+                    println!("{CONFIG_PATH}: '{line}': {error}");
+                }
+            }
+        }
+    }
+
     pub fn read_line(&mut self) -> String {
         let mut line = Vec::<char>::new();
 
@@ -142,7 +237,43 @@ impl Shell {
                         _ => {}
                     };
                 }
-                None => {}
+                None => {
+                    // print the prompt and the line again after the notice about new devices
+                    if self.storage.poll_hotplug() {
+                        self.print_shell();
+                        for char in &line {
+                            print!("{char}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // like read_line but without echo and history
+    fn read_password() -> String {
+        let mut password = String::new();
+
+        loop {
+            let key = uefi::system::with_stdin(|stdin| stdin.read_key().expect("Expected input"));
+            let Some(Key::Printable(key)) = key else {
+                continue;
+            };
+
+            match char::from(key) {
+                '\r' => {
+                    print!("\r\n");
+                    return password;
+                },
+                '\x08' => {
+                    if password.pop().is_some() {
+                        print!("\x08");
+                    }
+                },
+                key => {
+                    print!("*");
+                    password.push(key);
+                },
             }
         }
     }
@@ -162,24 +293,60 @@ impl Shell {
             self.cmd_history.push(command.to_string());
         }
         if let Some((program, args)) = self.parse_command(command) {
-            if let Err(error) = match alloc::string::ToString::to_string(&program).as_str() {
-                "help" => self.help(),
-                "exit" => self.exit(),
-                "ls" => self.ls(args),
-                "clear" => self.clear(),
-                "printmmap" => self.print_mmap(),
-                "cd" => self.cd(args),
-                "runefi" => self.run_efi(args),
-                "runkernel" => self.run_kernel(args),
-                "quickstart" => self.quickstart(args),
-                "quickstart_options" => self.quickstart_options(),
-                _ => simple_error!("Unknown command '{program}'"),
-            } {
+            if let Err(error) = self.check_command_allowed(&program).and_then(|_| self.execute_command(&program, args)) {
                 println!("{error}");
             }
         }
     }
 
+    fn locked(&self) -> bool {
+        (self.password.is_some() || self.password_required) && !self.unlocked
+    }
+
+    // only applies to typed commands, the config and quickstart options can run everything
+    fn check_command_allowed(&self, program: &str) -> SimpleResult<()> {
+        if self.locked() && !UNLOCKED_COMMANDS.contains(&program) {
+            return simple_error!("The shell is locked. Boot a quickstart option or type 'unlock'.");
+        }
+        if self.lockdown && LOCKDOWN_COMMANDS.contains(&program) {
+            return simple_error!("{program} is disabled in lockdown mode because Secure Boot is enabled, use a quickstart option");
+        }
+        if program == "password" {
+            return simple_error!("password can only be set in {CONFIG_PATH}");
+        }
+        Ok(())
+    }
+
+    fn execute_command(&mut self, program: &str, args: Vec<String>) -> SimpleResult<()> {
+        match program {
+            "help" => self.help(),
+            "exit" => self.exit(),
+            "ls" => self.ls(args),
+            "clear" => self.clear(),
+            "printmmap" => self.print_mmap(),
+            "acpi" => self.acpi(),
+            "cd" => self.cd(args),
+            "runefi" => self.run_efi(args),
+            "runkernel" => self.run_kernel(args),
+            "runmultiboot2" => self.run_multiboot2(args),
+            "runxen" => self.run_xen(args),
+            "runelf" => self.run_elf(args),
+            "runandroid" => self.run_android(args),
+            "entry" => self.add_entry(args),
+            "quickstart" => self.quickstart(args),
+            "quickstart_options" => self.quickstart_options(),
+            "gfxmode" => self.gfxmode(args),
+            "edid" => self.edid(),
+            "kernelinfo" => self.kernel_info(args),
+            "verify" => self.verify(args),
+            "sha256sum" => self.sha256sum(args),
+            "verify-manifest" => self.verify_manifest(args),
+            "password" => self.set_password(args),
+            "unlock" => self.unlock(),
+            _ => simple_error!("Unknown command '{program}'"),
+        }
+    }
+
     // this is just best-effort parsing so it's probably broken is some edge cases
     pub fn parse_command(&self, command: &str) -> Option<(String, Vec<String>)> {
         let mut cmd_parts = Vec::<String>::new();
@@ -244,10 +411,24 @@ impl Shell {
         println!("- ls [PATH]");
         println!("- clear");
         println!("- printmmap");
+        println!("- acpi");
         println!("- runefi [PATH]");
-        println!("- runkernel [PATH] [KERNEL-CMDLINE] [opt. RAMDISK]");
+        println!("- runkernel [PATH] [KERNEL-CMDLINE] [opt. RAMDISK] [opt. --dtb/--ima/--cc-blob PATH] [opt. --dry-run]");
+        println!("- runmultiboot2 [PATH] [CMDLINE] [opt. MODULE MODULE-CMDLINE]...");
+        println!("- runxen [XEN-PATH] [XEN-ARGS] [DOM0-KERNEL] [DOM0-CMDLINE] [opt. DOM0-RAMDISK]");
+        println!("- runelf [PATH] [CMDLINE] [opt. MODULE MODULE-CMDLINE]...");
+        println!("- runandroid [BOOT-IMG] [opt. EXTRA-CMDLINE] [opt. --vendor-boot PATH] [opt. --dry-run]");
+        println!("- entry [COMMAND] [ARGS]... (adds a quickstart option, meant for the config file)");
         println!("- quickstart_options");
         println!("- quickstart [IDX]");
+        println!("- gfxmode [opt. IDX or WIDTHxHEIGHT]");
+        println!("- edid");
+        println!("- kernelinfo [PATH]");
+        println!("- verify [PATH]");
+        println!("- sha256sum [PATH]...");
+        println!("- verify-manifest");
+        println!("- password [PBKDF2-HASH] (locks the shell, only in the config file)");
+        println!("- unlock");
 
         Ok(())
     }
@@ -255,6 +436,7 @@ impl Shell {
     // search all partitions for linux kernel images or the windows bootloader .efi
     pub fn find_quickstart_options(&mut self) -> SimpleResult<Vec<QuickstartOption>> {
         let mut quickstart_options: Vec<QuickstartOption> = Vec::new();
+        let xen_regex = Regex::new(r"^xen-(.+)\.gz$").unwrap();
 
         for storage_device in self.storage.devices()? {
             let StorageDevice::Drive { partitions, .. } = storage_device else {
@@ -296,6 +478,9 @@ impl Shell {
 
                     let mut kernels = alloc::collections::btree_map::BTreeMap::new();
                     let mut ramdisks = alloc::collections::btree_map::BTreeMap::new();
+                    let mut xens = alloc::collections::btree_map::BTreeMap::new();
+                    let mut android_boot = None;
+                    let mut android_vendor_boot = None;
 
                     for file in files {
                         if !file.is_regular_file() || file.size() < 1000 {
@@ -319,10 +504,36 @@ impl Shell {
                             if let Some(version) = caps.get(2) {
                                 ramdisks.insert(version.as_str().to_string(), file_path);
                             }
+                        } else if let Some(caps) = xen_regex.captures(&file_name) {
+                            if let Some(version) = caps.get(1) {
+                                xens.insert(version.as_str().to_string(), file_path);
+                            }
+                        } else if file_name == "boot.img" {
+                            android_boot = Some(file_path);
+                        } else if file_name == "vendor_boot.img" {
+                            android_vendor_boot = Some(file_path);
                         }
                     }
 
+                    if let Some(boot_path) = android_boot {
+                        quickstart_options.push(QuickstartOption::Android { boot_path, vendor_boot_path: android_vendor_boot });
+                    }
+
+                    // every kernel can also be started as dom0 of the newest Xen next to it
+                    let newest_xen = xens.values().next_back();
+
                     for (version, kernel_path) in kernels {
+                        if let Some(xen_path) = newest_xen {
+                            quickstart_options.push(
+                                QuickstartOption::Xen {
+                                    xen_path: xen_path.clone(),
+                                    kernel_path: kernel_path.clone(),
+                                    ramdisk_path: ramdisks.get(&version).cloned(),
+                                    cmdline: alloc::format!("root=/dev/{}", partition_name)
+                                }
+                            );
+                        }
+
                         quickstart_options.push(
                             QuickstartOption::Kernel {
                                 kernel_path: kernel_path.clone(),
@@ -362,6 +573,29 @@ impl Shell {
 
                 return self.run_kernel(args);
             },
+            Some(QuickstartOption::Android { boot_path, vendor_boot_path }) => {
+                let mut args = alloc::vec![boot_path.into()];
+
+                if let Some(vendor_boot_path) = vendor_boot_path {
+                    args.push("--vendor-boot".into());
+                    args.push(vendor_boot_path.into());
+                }
+
+                self.run_android(args)
+            },
+            Some(QuickstartOption::Xen { xen_path, kernel_path, cmdline, ramdisk_path }) => {
+                let mut args = alloc::vec![xen_path.into(), String::new(), kernel_path.into(), cmdline.clone()];
+
+                if let Some(ramdisk_path) = ramdisk_path {
+                    args.push(ramdisk_path.into());
+                }
+
+                self.run_xen(args)
+            },
+            Some(QuickstartOption::Entry { program, args }) => {
+                let (program, args) = (program.clone(), args.clone());
+                self.execute_command(&program, args)
+            },
             None => simple_error!("{quickstart_idx} is out of range"),
         }
     }
@@ -386,6 +620,31 @@ impl Shell {
                         println!("[{idx}] runkernel {kernel_path} '{cmdline}'");
                     }
                 },
+                QuickstartOption::Android { boot_path, vendor_boot_path } => {
+                    if let Some(vendor_boot_path) = &vendor_boot_path {
+                        println!("[{idx}] runandroid {boot_path} --vendor-boot {vendor_boot_path}");
+                    } else {
+                        println!("[{idx}] runandroid {boot_path}");
+                    }
+                },
+                QuickstartOption::Xen { xen_path, kernel_path, cmdline, ramdisk_path } => {
+                    if let Some(ramdisk_path) = &ramdisk_path {
+                        println!("[{idx}] runxen {xen_path} '' {kernel_path} '{cmdline}' {ramdisk_path}");
+                    } else {
+                        println!("[{idx}] runxen {xen_path} '' {kernel_path} '{cmdline}'");
+                    }
+                },
+                QuickstartOption::Entry { program, args } => {
+                    print!("[{idx}] {program}");
+                    for arg in args {
+                        if arg.is_empty() || arg.contains(' ') {
+                            print!(" '{arg}'");
+                        } else {
+                            print!(" {arg}");
+                        }
+                    }
+                    println!();
+                },
             }
         }
         Ok(())
@@ -401,8 +660,15 @@ impl Shell {
             path.push(&args[0]);
         }
 
-        if let Some(partition_name) = path.components.first() {
-            let partition = self.storage.partition_by_name(partition_name)?;
+        if let Some(by_directory) = path.by_directory() {
+            for partition in self.storage.partitions()? {
+                if let Some(name) = partition.name_in(by_directory) {
+                    println!("{name} -> {}", partition.linux_name());
+                }
+            }
+            Ok(())
+        } else if let Some(partition_name) = path.partition_name() {
+            let partition = self.storage.partition_by_name(&partition_name)?;
 
             let Some(fs) = partition.fs() else {
                 return simple_error!("The partition's filesystem could not be read.");
@@ -420,8 +686,17 @@ impl Shell {
                 }
             }
         } else {
-            for partition in self.storage.partitions()? {
-                println!("{partition}");
+            for device in self.storage.devices()? {
+                println!("{device}");
+
+                if let StorageDevice::Drive { partitions, .. } = device {
+                    for partition in partitions {
+                        println!("  {partition}");
+                    }
+                }
+            }
+            for by_directory in BY_DIRECTORIES {
+                println!("{by_directory}/");
             }
             Ok(())
         }
@@ -442,6 +717,148 @@ impl Shell {
         Ok(())
     }
 
+    // list the ACPI tables found through the RSDP
+    fn acpi(&mut self) -> SimpleResult<()> {
+        use crate::acpi::ascii_to_string;
+
+        let rsdp = crate::acpi::rsdp()?;
+        let revision = rsdp.revision;
+        println!(
+            "RSDP at {:#x} revision: {} OEM: {}",
+            crate::acpi::rsdp_addr().unwrap_or(0),
+            revision,
+            ascii_to_string(&rsdp.oem_id)
+        );
+
+        for table in crate::acpi::tables()? {
+            let header = table.header;
+            let length = header.length;
+
+            println!(
+                "{:#012x} {}  OEM: {:<6} {:<8}  length: {:>6}{}",
+                table.addr,
+                ascii_to_string(&header.signature),
+                ascii_to_string(&header.oem_id),
+                ascii_to_string(&header.oem_table_id),
+                length,
+                if table.checksum_valid { "" } else { "  (invalid checksum)" }
+            );
+        }
+        Ok(())
+    }
+
+    // list the graphics modes or set one (the kernel gets the mode that is set when it starts)
+    fn gfxmode(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        match args.len() {
+            0 => crate::video::print_modes(),
+            1 => crate::video::set_mode(&args[0]),
+            _ => simple_error!("gfxmode needs 0 or 1 arguments"),
+        }
+    }
+
+    fn edid(&mut self) -> SimpleResult<()> {
+        let Some(edid) = crate::video::read_edid() else {
+            return simple_error!("The firmware does not provide an EDID for the display.");
+        };
+        crate::video::print_edid(&edid)
+    }
+
+    fn kernel_info(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        if args.len() != 1 {
+            return simple_error!("kernelinfo needs one argument");
+        }
+
+        let mut kernel_image_path = self.cwd.clone();
+        kernel_image_path.push(&args[0]);
+
+        let kernel = match self.storage.read_file(&kernel_image_path) {
+            Ok(kernel) => kernel,
+            Err(err) => return simple_error!("Could not read kernel image: {err}"),
+        };
+        crate::kernel::print_kernel_info(&kernel)
+    }
+
+    fn verify(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        if args.len() != 1 {
+            return simple_error!("verify needs one argument");
+        }
+
+        let mut path = self.cwd.clone();
+        path.push(&args[0]);
+
+        let image = match self.storage.read_file(&path) {
+            Ok(image) => image,
+            Err(err) => return simple_error!("Could not read image: {err}"),
+        };
+
+        match crate::authenticode::verify(&image, true) {
+            Ok(()) => println!("The image is trusted"),
+            Err(err) => println!("The image is NOT trusted: {err}"),
+        }
+        Ok(())
+    }
+
+    fn sha256sum(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        if args.is_empty() {
+            return simple_error!("sha256sum needs at least one argument");
+        }
+
+        for arg in args {
+            let mut path = self.cwd.clone();
+            path.push(&arg);
+
+            match self.storage.read_file(&path) {
+                Ok(data) => println!("{}  {path}", crate::manifest::to_hex(&crate::manifest::sha256(&data))),
+                Err(err) => println!("{arg}: {err}"),
+            }
+        }
+        Ok(())
+    }
+
+    // reads every file in the manifest, read_file refuses those that do not match
+    fn verify_manifest(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        if !args.is_empty() {
+            return simple_error!("verify-manifest takes no arguments");
+        }
+
+        let Some(manifest) = self.storage.manifest()? else {
+            return simple_error!("There is no {MANIFEST_PATH} on the ESP");
+        };
+
+        println!("{MANIFEST_PATH}: {} entries, {}", manifest.entries().len(), if manifest.signed { "signature is valid" } else { "not signed" });
+
+        let entries = manifest.entries().to_vec();
+        let esp = self.storage.esp_partition_name()?;
+        let mut failed = 0;
+
+        for (entry, _) in &entries {
+            let path = if entry.starts_with('/') {
+                FsPath::parse(entry)?
+            } else if let Some(esp) = &esp {
+                let mut path = FsPath::new();
+                path.push(esp).push(entry);
+                path
+            } else {
+                println!("{entry}: FAILED (the ESP was not found)");
+                failed += 1;
+                continue;
+            };
+
+            match self.storage.read_file(&path) {
+                Ok(_) => println!("{entry}: OK"),
+                Err(err) => {
+                    println!("{entry}: FAILED ({err})");
+                    failed += 1;
+                },
+            }
+        }
+
+        if failed > 0 {
+            return simple_error!("{failed} of {} files could not be verified", entries.len());
+        }
+        Ok(())
+    }
+
     fn cd(&mut self, args: Vec<String>) -> SimpleResult<()> {
         if args.len() != 1 {
             return simple_error!("cd needs one argument");
@@ -450,12 +867,12 @@ impl Shell {
         let mut path = self.cwd.clone();
         path.push(&args[0]);
 
-        let Some(partition_name) = path.components.first() else {
-            self.cwd = FsPath::new();
+        let Some(partition_name) = path.partition_name() else {
+            self.cwd = path; // / or a by-* directory
             return Ok(());
         };
 
-        let partition = self.storage.partition_by_name(partition_name)?;
+        let partition = self.storage.partition_by_name(&partition_name)?;
 
         let Some(fs) = partition.fs() else {
             return simple_error!("The partition's filesystem could not be read.");
@@ -480,58 +897,228 @@ impl Shell {
         let mut path = self.cwd.clone();
         path.push(&args[0]);
 
-        let Some(partition_name) = path.components.first().cloned() else {
-            return simple_error!("/ is not an EFI -.-");
+        let Some(partition_name) = path.partition_name() else {
+            return simple_error!("{path} is not an EFI -.-");
         };
 
+        println!("Loading image into memory...");
+        let image = self.storage.read_file(&path)?;
+        crate::tpm::measure_file(&image, &path.to_string())?;
+
+        let verify = crate::secure_boot::verification_required();
+        if verify {
+            crate::secure_boot::verify_image(&image, "EFI application")?;
+        }
+        let in_manifest = self.storage.manifest()?.is_some();
+
+        let path_string = path.to_string();
         let partition = self.storage.partition_by_name(&partition_name)?;
+        let file_dpath = partition.device_path_for_file::<String>(path.into());
 
-        let Some(fs) = partition.fs() else {
-            return simple_error!("The partition's filesystem could not be read.");
+        if file_dpath.is_none() {
+            println!("Could not get device path for the file. Starting the EFI might work anyway.");
+        }
+
+        // the verified buffer is started so the file cannot be swapped in between
+        let loaded_image = if verify {
+            crate::secure_boot::load_verified_image(&image, file_dpath.as_deref())
+        } else if in_manifest {
+            uefi::boot::load_image(
+                uefi::boot::image_handle(),
+                uefi::boot::LoadImageSource::FromBuffer { buffer: &image, file_path: file_dpath.as_deref() },
+            )
+        } else {
+            let Some(device_path) = file_dpath.as_deref() else {
+                return simple_error!("Could not get the device path of {path_string}, cannot start it");
+            };
+
+            uefi::boot::load_image(
+                uefi::boot::image_handle(),
+                uefi::boot::LoadImageSource::FromDevicePath { device_path, boot_policy: BootPolicy::ExactMatch },
+            )
         };
 
-        println!("Loading image into memory...");
-        match fs.read_file(&path.path_on_partition()) {
-            Err(FileError::NotAFile) => simple_error!("{path} is not a file"),
-            Err(FileError::NotFound) => simple_error!("{path} not found."),
-            Err(_) => simple_error!("An error occurred."),
-            Ok(_) => {
-                let file_dpath = partition.device_path_for_file::<String>(path.into());
+        match loaded_image {
+            Ok(loaded_image) => {
+                println!("Starting image...\n\n");
+                uefi::boot::stall(1_500_000); // time to read logs
 
-                if file_dpath.is_none() {
-                    println!("Could not get device path for the file. Starting the EFI might work anyway.");
+                if let Err(err) = uefi::boot::start_image(loaded_image) {
+                    return simple_error!("Could not start EFI because of an error: {err}");
+                } else {
+                    println!("The EFI application exited");
                 }
+            }
+            Err(err) => {
+                return simple_error!(
+                    "Failed to load EFI image into buffer because of: {err}"
+                )
+            }
+        }
 
-                match uefi::boot::load_image(
-                    uefi::boot::image_handle(),
-                    uefi::boot::LoadImageSource::FromDevicePath {
-                        device_path: &file_dpath.unwrap(),
-                        boot_policy: BootPolicy::ExactMatch,
-                    },
-                ) {
-                    Ok(loaded_image) => {
-                        println!("Starting image...\n\n");
-                        uefi::boot::stall(1_500_000); // time to read logs
-
-                        if let Err(err) = uefi::boot::start_image(loaded_image) {
-                            return simple_error!("Could not start EFI because of an error: {err}");
-                        } else {
-                            println!("The EFI application exited");
-                        }
-                    }
-                    Err(err) => {
-                        return simple_error!(
-                            "Failed to load EFI image into buffer because of: {err}"
-                        )
-                    }
-                }
+        Ok(())
+    }
 
-                Ok(())
+    // modules are given as pairs of path and command line ('' for an empty one)
+    pub fn run_multiboot2(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        if args.len() < 2 || !args.len().is_multiple_of(2) {
+            return simple_error!("runmultiboot2 needs a path, a command line and pairs of module paths and command lines");
+        }
+
+        let mut image_path = self.cwd.clone();
+        image_path.push(&args[0]);
+
+        println!("Loading Multiboot2 image into memory...");
+        let image = match self.storage.read_file(&image_path) {
+            Ok(image) => image,
+            Err(err) => return simple_error!("Could not read Multiboot2 image: {err}"),
+        };
+
+        let mut multiboot2 = crate::multiboot2::Multiboot2::new(image)?;
+
+        for module_args in args[2..].chunks(2) {
+            let mut module_path = self.cwd.clone();
+            module_path.push(&module_args[0]);
+
+            println!("Loading module {module_path} into memory...");
+            let module = match self.storage.read_file(&module_path) {
+                Ok(module) => module,
+                Err(err) => return simple_error!("Could not read module {module_path}: {err}"),
+            };
+            multiboot2.add_module(module, &module_args[1]);
+        }
+
+        multiboot2.start(&args[1])
+    }
+
+    // Xen and its dom0 kernel and ramdisk are started as Multiboot2 image and modules
+    pub fn run_xen(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        if args.len() < 4 || args.len() > 5 {
+            return simple_error!("runxen needs four or five arguments");
+        }
+
+        let mut xen_path = self.cwd.clone();
+        xen_path.push(&args[0]);
+
+        println!("Loading Xen into memory...");
+        let mut xen = match self.storage.read_file(&xen_path) {
+            Ok(xen) => xen,
+            Err(err) => return simple_error!("Could not read Xen image: {err}"),
+        };
+
+        if crate::decompress::is_gzip(&xen) {
+            println!("Decompressing Xen...");
+            xen = crate::decompress::gunzip(&xen)?;
+        }
+
+        let mut multiboot2 = crate::multiboot2::Multiboot2::new(xen)?;
+
+        // Xen strips the first word of its and dom0's command line unless the loader is GRUB 2,
+        // so they start with the file name like with GRUB 1
+        let mut kernel_path = self.cwd.clone();
+        kernel_path.push(&args[2]);
+
+        println!("Loading kernel image into memory...");
+        let kernel = match self.storage.read_file(&kernel_path) {
+            Ok(kernel) => kernel,
+            Err(err) => return simple_error!("Could not read kernel image: {err}"),
+        };
+        multiboot2.add_module(kernel, &alloc::format!("{} {}", args[2], args[3]));
+
+        if let Some(ramdisk) = args.get(4) {
+            let mut ramdisk_path = self.cwd.clone();
+            ramdisk_path.push(ramdisk);
+
+            println!("Loading ramdisk image into memory...");
+            let ramdisk_image = match self.storage.read_file(&ramdisk_path) {
+                Ok(ramdisk_image) => ramdisk_image,
+                Err(err) => return simple_error!("Could not read ramdisk image: {err}"),
+            };
+            multiboot2.add_module(ramdisk_image, ramdisk);
+        }
+
+        multiboot2.start(&alloc::format!("{} {}", args[0], args[1]))
+    }
+
+    // static ELF64 executables get the boot info described in documentation/elf_boot_protocol.md
+    pub fn run_elf(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        if args.len() < 2 || !args.len().is_multiple_of(2) {
+            return simple_error!("runelf needs a path, a command line and pairs of module paths and command lines");
+        }
+
+        let mut image_path = self.cwd.clone();
+        image_path.push(&args[0]);
+
+        println!("Loading ELF executable into memory...");
+        let image = match self.storage.read_file(&image_path) {
+            Ok(image) => image,
+            Err(err) => return simple_error!("Could not read ELF executable: {err}"),
+        };
+
+        let mut executable = crate::elfboot::ElfExecutable::new(image)?;
+
+        for module_args in args[2..].chunks(2) {
+            let mut module_path = self.cwd.clone();
+            module_path.push(&module_args[0]);
+
+            println!("Loading module {module_path} into memory...");
+            let module = match self.storage.read_file(&module_path) {
+                Ok(module) => module,
+                Err(err) => return simple_error!("Could not read module {module_path}: {err}"),
+            };
+            executable.add_module(module, &module_args[1]);
+        }
+
+        executable.start(&args[1])
+    }
+
+    // adds a quickstart option that executes the given command
+    fn add_entry(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        let Some((program, args)) = args.split_first() else {
+            return simple_error!("entry needs a command");
+        };
+
+        self.quickstart_options.push(QuickstartOption::Entry { program: program.clone(), args: args.to_vec() });
+        Ok(())
+    }
+
+    fn set_password(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        if args.len() != 1 {
+            return simple_error!("password needs one argument");
+        }
+
+        // a password that was meant to be set but is broken must not leave the shell open
+        self.unlocked = false;
+        self.password_required = true;
+        self.password = Some(PasswordHash::parse(&args[0])?);
+        Ok(())
+    }
+
+    fn unlock(&mut self) -> SimpleResult<()> {
+        let Some(password_hash) = &self.password else {
+            if self.password_required {
+                return simple_error!("{CONFIG_PATH} was rejected or has an invalid password, the shell cannot be unlocked");
             }
+            return simple_error!("There is no password set");
+        };
+
+        print!("Password: ");
+        let password = Shell::read_password();
+
+        if !password_hash.verify(&password) {
+            uefi::boot::stall(2_000_000); // slow down guessing
+            return simple_error!("Wrong password");
         }
+
+        self.unlocked = true;
+        println!("The shell is unlocked");
+        Ok(())
     }
 
     pub fn run_kernel(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        let CommandArgs { positional: args, options, flags } =
+            Shell::split_options(args, &["--dtb", "--ima", "--cc-blob"], &["--dry-run"])?;
+
         if args.len() < 2 || args.len() > 3 {
             return simple_error!("runkernel needs two or three arguments");
         }
@@ -557,6 +1144,106 @@ impl Shell {
 
         let kernel_cmdline = &args[1];
 
-        crate::kernel::Kernel::new(kernel)?.start(kernel_cmdline, ramdisk)
+        let mut kernel = crate::kernel::Kernel::new(kernel)?;
+
+        // additional blobs passed to the kernel as setup_data nodes
+        for (option, value) in options {
+            let mut blob_path = self.cwd.clone();
+            blob_path.push(&value);
+
+            let blob = match self.storage.read_file(&blob_path) {
+                Ok(blob) => blob,
+                Err(err) => return simple_error!("Could not read {blob_path}: {err}"),
+            };
+
+            kernel.add_setup_data(match option.as_str() {
+                "--dtb" => SetupDataEntry::Dtb(&blob),
+                "--ima" => SetupDataEntry::Ima(&blob),
+                "--cc-blob" => SetupDataEntry::CcBlob(&blob),
+                _ => unreachable!(),
+            })?;
+        }
+
+        if flags.iter().any(|flag| flag == "--dry-run") {
+            return kernel.dry_run(kernel_cmdline, ramdisk);
+        }
+
+        kernel.start(kernel_cmdline, ramdisk)
+    }
+
+    // the kernel, ramdisk and cmdline come from the boot image (and vendor_boot), the user can add to the cmdline
+    pub fn run_android(&mut self, args: Vec<String>) -> SimpleResult<()> {
+        let CommandArgs { positional: args, options, flags } =
+            Shell::split_options(args, &["--vendor-boot"], &["--dry-run"])?;
+
+        if args.is_empty() || args.len() > 2 {
+            return simple_error!("runandroid needs one or two arguments");
+        }
+
+        let mut boot_path = self.cwd.clone();
+        boot_path.push(&args[0]);
+
+        println!("Loading Android boot image into memory...");
+        let boot_image = match self.storage.read_file(&boot_path) {
+            Ok(boot_image) => boot_image,
+            Err(err) => return simple_error!("Could not read Android boot image: {err}"),
+        };
+
+        let mut vendor_boot_image = None;
+        if let Some((_, vendor_boot)) = options.first() {
+            let mut vendor_boot_path = self.cwd.clone();
+            vendor_boot_path.push(vendor_boot);
+
+            println!("Loading vendor_boot image into memory...");
+            vendor_boot_image = match self.storage.read_file(&vendor_boot_path) {
+                Ok(vendor_boot_image) => Some(vendor_boot_image),
+                Err(err) => return simple_error!("Could not read vendor_boot image: {err}"),
+            };
+        }
+
+        let extra_cmdline = args.get(1).map(String::as_str).unwrap_or("");
+        let android = crate::android::AndroidBoot::new(&boot_image, vendor_boot_image.as_deref(), extra_cmdline)?;
+        drop((boot_image, vendor_boot_image));     // the kernel and ramdisk were copied out of them
+
+        println!("Kernel command line: {}", android.cmdline);
+        let mut kernel = crate::kernel::Kernel::new(android.kernel)?;
+
+        if flags.iter().any(|flag| flag == "--dry-run") {
+            return kernel.dry_run(&android.cmdline, android.ramdisk);
+        }
+
+        kernel.start(&android.cmdline, android.ramdisk)
+    }
+
+    // separates "--option VALUE" pairs and "--flag"s from the positional arguments
+    fn split_options(args: Vec<String>, known_options: &[&str], known_flags: &[&str]) -> SimpleResult<CommandArgs> {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        let mut flags = Vec::new();
+        let mut iter = args.into_iter();
+
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg);
+                continue;
+            }
+
+            if known_flags.contains(&arg.as_str()) {
+                flags.push(arg);
+                continue;
+            }
+
+            if !known_options.contains(&arg.as_str()) {
+                return simple_error!("Unknown option '{arg}'");
+            }
+
+            let Some(value) = iter.next() else {
+                return simple_error!("Option '{arg}' needs a value");
+            };
+
+            options.push((arg, value));
+        }
+
+        Ok(CommandArgs { positional, options, flags })
     }
 }
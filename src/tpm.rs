@@ -0,0 +1,59 @@
+/*
+This file measures what is booted into the TPM 2.0 through EFI_TCG2_PROTOCOL (measured boot). Files (kernels, modules,
+initrds, chainloaded EFI applications and the config file) are measured into PCR 9 and kernel command lines into
+PCR 12 like the kernel's EFI stub and systemd-stub do it. Every measurement is also recorded in the firmware's
+event log so it can be replayed by an attestation server.
+Without a TPM nothing is measured.
+https://trustedcomputinggroup.org/resource/tcg-efi-protocol-specification/
+*/
+
+extern crate alloc;
+
+use uefi::boot;
+use uefi::proto::tcg::v2::{HashLogExtendEventFlags, PcrEventInputs, Tcg};
+use uefi::proto::tcg::{EventType, PcrIndex};
+
+use crate::simple_error::{simple_error, SimpleResult};
+
+pub const PCR_FILES: u32 = 9;
+pub const PCR_CMDLINE: u32 = 12;
+
+// None if there is no TPM 2.0 (or it is disabled)
+fn open_tcg() -> Option<boot::ScopedProtocol<Tcg>> {
+    let handle = boot::get_handle_for_protocol::<Tcg>().ok()?;
+    let mut tcg = crate::disk::open_protocol_unsafe::<Tcg>(handle).ok()?;
+
+    tcg.get_capability().ok()?.tpm_present().then_some(tcg)
+}
+
+pub fn tpm_present() -> bool {
+    open_tcg().is_some()
+}
+
+// extends the PCR with the hash of data and logs an EV_IPL event with the description as event data
+fn measure(pcr: u32, data: &[u8], description: &str) -> SimpleResult<()> {
+    let Some(mut tcg) = open_tcg() else {
+        return Ok(());
+    };
+
+    let event = match PcrEventInputs::new_in_box(PcrIndex(pcr), EventType::IPL, description.as_bytes()) {
+        Ok(event) => event,
+        Err(err) => return simple_error!("Could not create the TPM event for {description}: {err}"),
+    };
+
+    if let Err(err) = tcg.hash_log_extend_event(HashLogExtendEventFlags::empty(), data, &event) {
+        return simple_error!("Could not measure {description} into PCR {pcr}: {err}");
+    }
+    Ok(())
+}
+
+pub fn measure_file(data: &[u8], description: &str) -> SimpleResult<()> {
+    measure(PCR_FILES, data, description)
+}
+
+// The kernel's EFI stub and systemd-stub measure the UTF-16LE command line including the terminating NUL, so we do
+// the same to keep the PCR values predictable with systemd-pcrlock and ukify.
+pub fn measure_cmdline(cmdline: &str) -> SimpleResult<()> {
+    let utf16: alloc::vec::Vec<u8> = cmdline.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+    measure(PCR_CMDLINE, &utf16, cmdline)
+}
@@ -0,0 +1,252 @@
+// This file contains helpers for the UEFI Graphics Output Protocol (GOP) which provides the framebuffer we pass to the kernel.
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use uefi::boot::{self, ScopedProtocol};
+use uefi::println;
+use uefi::proto::console::gop::{GraphicsOutput, Mode, ModeInfo, PixelFormat};
+use uefi::proto::unsafe_protocol;
+
+use crate::disk::open_protocol_unsafe;
+use crate::simple_error::{simple_error, SimpleResult};
+
+// Position and size of the color channels in a pixel like in the kernel's screen_info
+#[derive(Debug, Copy, Clone)]
+pub struct PixelLayout {
+    pub red: (u8, u8),      // (position, size)
+    pub green: (u8, u8),
+    pub blue: (u8, u8),
+    pub reserved: (u8, u8),
+    pub bits_per_pixel: u16,
+}
+
+// None on systems without a GOP (e.g. serial console only)
+pub fn open_gop() -> Option<ScopedProtocol<GraphicsOutput>> {
+    let gop_handle = boot::get_handle_for_protocol::<GraphicsOutput>().ok()?;
+    open_protocol_unsafe::<GraphicsOutput>(gop_handle).ok()
+}
+
+// None if the mode has no framebuffer (BltOnly)
+pub fn pixel_layout(mode_info: &ModeInfo) -> Option<PixelLayout> {
+    match mode_info.pixel_format() {
+        // from the UEFI spec: byte 0 is red for RGB and blue for BGR, byte 3 is reserved
+        PixelFormat::Rgb => Some(PixelLayout {
+            red: (0, 8),
+            green: (8, 8),
+            blue: (16, 8),
+            reserved: (24, 8),
+            bits_per_pixel: 32,
+        }),
+        PixelFormat::Bgr => Some(PixelLayout {
+            red: (16, 8),
+            green: (8, 8),
+            blue: (0, 8),
+            reserved: (24, 8),
+            bits_per_pixel: 32,
+        }),
+        PixelFormat::Bitmask => {
+            let bitmask = mode_info.pixel_bitmask()?;
+
+            let red = mask_to_pos_and_size(bitmask.red);
+            let green = mask_to_pos_and_size(bitmask.green);
+            let blue = mask_to_pos_and_size(bitmask.blue);
+            let reserved = mask_to_pos_and_size(bitmask.reserved);
+
+            // same as the EFI stub of the kernel
+            let bits_per_pixel = (red.1 + green.1 + blue.1 + reserved.1) as u16;
+
+            Some(PixelLayout { red, green, blue, reserved, bits_per_pixel })
+        }
+        PixelFormat::BltOnly => None,
+    }
+}
+
+// assumes the mask is contiguous
+fn mask_to_pos_and_size(mask: u32) -> (u8, u8) {
+    if mask == 0 {
+        return (0, 0);
+    }
+
+    let pos = mask.trailing_zeros();
+    let size = 32 - mask.leading_zeros() - pos;
+    (pos as u8, size as u8)
+}
+
+fn modes(gop: &GraphicsOutput) -> Vec<Mode> {
+    gop.modes().collect()
+}
+
+fn is_current_mode(gop: &GraphicsOutput, mode: &Mode) -> bool {
+    let current = gop.current_mode_info();
+    let info = mode.info();
+
+    current.resolution() == info.resolution()
+        && current.pixel_format() == info.pixel_format()
+        && current.stride() == info.stride()
+}
+
+pub fn print_modes() -> SimpleResult<()> {
+    let Some(gop) = open_gop() else {
+        return simple_error!("No Graphics Output Protocol found.");
+    };
+
+    for (idx, mode) in modes(&gop).iter().enumerate() {
+        let (width, height) = mode.info().resolution();
+
+        println!(
+            "{} [{idx}] {width}x{height} {:?}",
+            if is_current_mode(&gop, mode) { "*" } else { " " },
+            mode.info().pixel_format()
+        );
+    }
+    Ok(())
+}
+
+// the mode can be given as its index or as WIDTHxHEIGHT
+pub fn set_mode(mode_str: &str) -> SimpleResult<()> {
+    let Some(mut gop) = open_gop() else {
+        return simple_error!("No Graphics Output Protocol found.");
+    };
+
+    let modes = modes(&gop);
+
+    let mode = if let Some((width, height)) = mode_str.split_once('x') {
+        let (Ok(width), Ok(height)) = (width.parse::<usize>(), height.parse::<usize>()) else {
+            return simple_error!("Could not parse '{mode_str}' as WIDTHxHEIGHT");
+        };
+
+        // prefer modes with a framebuffer
+        modes
+            .iter()
+            .filter(|mode| mode.info().resolution() == (width, height))
+            .max_by_key(|mode| pixel_layout(mode.info()).is_some())
+    } else {
+        let Ok(idx) = mode_str.parse::<usize>() else {
+            return simple_error!("Could not parse '{mode_str}' as mode index or WIDTHxHEIGHT");
+        };
+        modes.get(idx)
+    };
+
+    let Some(mode) = mode else {
+        return simple_error!("There is no graphics mode {mode_str}. Use 'gfxmode' to list all modes.");
+    };
+
+    gop.set_mode(mode)?;
+    Ok(())
+}
+
+// EFI_EDID_ACTIVE_PROTOCOL and EFI_EDID_DISCOVERED_PROTOCOL have the same layout; the active EDID is preferred
+// because it is the one the firmware actually uses (e.g. overridden by the platform).
+// https://uefi.org/specs/UEFI/2.10/12_Protocols_Console_Support.html#edid-active-protocol
+#[repr(C)]
+#[unsafe_protocol("bd8c1056-9f36-44ec-92a8-a6337f817986")]
+struct EdidActive {
+    size_of_edid: u32,
+    edid: *const u8,
+}
+
+#[repr(C)]
+#[unsafe_protocol("1c0c34f6-d380-41fa-a049-8ad06c1a66aa")]
+struct EdidDiscovered {
+    size_of_edid: u32,
+    edid: *const u8,
+}
+
+pub const EDID_BLOCK_SIZE: usize = 128;
+
+// reads the EDID of the display connected to the GOP
+pub fn read_edid() -> Option<Vec<u8>> {
+    let gop_handle = boot::get_handle_for_protocol::<GraphicsOutput>().ok()?;
+
+    let (size, ptr) = if let Ok(edid) = open_protocol_unsafe::<EdidActive>(gop_handle) {
+        (edid.size_of_edid, edid.edid)
+    } else {
+        let edid = open_protocol_unsafe::<EdidDiscovered>(gop_handle).ok()?;
+        (edid.size_of_edid, edid.edid)
+    };
+
+    if size == 0 || ptr.is_null() {
+        return None;
+    }
+
+    Some(unsafe { core::slice::from_raw_parts(ptr, size as usize) }.to_vec())
+}
+
+// https://en.wikipedia.org/wiki/Extended_Display_Identification_Data#EDID_1.4_data_format
+pub fn print_edid(edid: &[u8]) -> SimpleResult<()> {
+    const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+
+    if edid.len() < EDID_BLOCK_SIZE || edid[..8] != HEADER {
+        return simple_error!("The EDID has an invalid header");
+    }
+
+    let checksum_valid = edid[..EDID_BLOCK_SIZE].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0;
+
+    // three letters with 5 bits each ('A' = 1)
+    let manufacturer_id = u16::from_be_bytes([edid[8], edid[9]]);
+    let manufacturer: String = [10, 5, 0]
+        .iter()
+        .map(|shift| (b'A' - 1 + ((manufacturer_id >> shift) & 0x1F) as u8) as char)
+        .collect();
+
+    println!("Manufacturer: {manufacturer}  product code: {:#06x}  serial: {}",
+        u16::from_le_bytes([edid[10], edid[11]]),
+        u32::from_le_bytes([edid[12], edid[13], edid[14], edid[15]]));
+    println!("Manufactured: week {} of {}  EDID version: {}.{}{}",
+        edid[16], 1990 + edid[17] as u32, edid[18], edid[19],
+        if checksum_valid { "" } else { "  (invalid checksum)" });
+    println!("Size: {} cm x {} cm  extension blocks: {}", edid[21], edid[22], edid[126]);
+
+    // the first detailed timing descriptor is the preferred mode
+    let descriptor = &edid[54..72];
+    let pixel_clock = u16::from_le_bytes([descriptor[0], descriptor[1]]);
+    if pixel_clock != 0 {
+        let h_active = descriptor[2] as u32 | ((descriptor[4] as u32 & 0xF0) << 4);
+        let h_blank = descriptor[3] as u32 | ((descriptor[4] as u32 & 0x0F) << 8);
+        let v_active = descriptor[5] as u32 | ((descriptor[7] as u32 & 0xF0) << 4);
+        let v_blank = descriptor[6] as u32 | ((descriptor[7] as u32 & 0x0F) << 8);
+
+        // pixel clock is in 10 kHz units
+        let refresh_rate = pixel_clock as u64 * 10_000 / ((h_active + h_blank) as u64 * (v_active + v_blank) as u64).max(1);
+        println!("Preferred mode: {h_active}x{v_active}@{refresh_rate}Hz  pixel clock: {}.{:02} MHz",
+            pixel_clock / 100, pixel_clock % 100);
+    }
+
+    println!("Supported resolutions:");
+
+    // established timings (bytes 35-37); bit 7 of byte 35 comes first
+    const ESTABLISHED_TIMINGS: [(u32, u32, u32); 17] = [
+        (720, 400, 70), (720, 400, 88), (640, 480, 60), (640, 480, 67),
+        (640, 480, 72), (640, 480, 75), (800, 600, 56), (800, 600, 60),
+        (800, 600, 72), (800, 600, 75), (832, 624, 75), (1024, 768, 87),
+        (1024, 768, 60), (1024, 768, 70), (1024, 768, 75), (1280, 1024, 75),
+        (1152, 870, 75),
+    ];
+
+    for (idx, (width, height, refresh_rate)) in ESTABLISHED_TIMINGS.iter().enumerate() {
+        let byte = edid[35 + idx / 8];
+        if byte & (0x80 >> (idx % 8)) != 0 {
+            println!("  {width}x{height}@{refresh_rate}Hz");
+        }
+    }
+
+    // standard timings (bytes 38-53)
+    for timing in edid[38..54].chunks(2) {
+        if timing == [0x01, 0x01] || timing[0] == 0 {
+            continue; // unused
+        }
+
+        let width = (timing[0] as u32 + 31) * 8;
+        let height = match timing[1] >> 6 {
+            0b00 => width * 10 / 16,
+            0b01 => width * 3 / 4,
+            0b10 => width * 4 / 5,
+            _ => width * 9 / 16,
+        };
+        let refresh_rate = (timing[1] & 0x3F) as u32 + 60;
+
+        println!("  {width}x{height}@{refresh_rate}Hz");
+    }
+    Ok(())
+}